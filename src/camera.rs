@@ -0,0 +1,452 @@
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+use bevy::{
+    input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel},
+    prelude::*,
+    render::camera::{Camera, DepthCalculation, ScalingMode},
+};
+use bevy_egui::EguiContext;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    kinematics::Manipulability,
+    robot::{ActiveRobot, Robot, JOINT_NAMES},
+};
+
+// Radians per pixel of mouse motion
+const ORBIT_SPEED: f32 = 0.005;
+// Fraction of the focus distance per pixel, so panning feels the same at any zoom
+const PAN_SPEED: f32 = 0.0015;
+// Fraction of the focus distance per wheel line
+const ZOOM_STEP: f32 = 0.1;
+const MIN_DISTANCE: f32 = 0.2;
+const MAX_DISTANCE: f32 = 100.0;
+// Orthographic views are rendered from this far back so nothing gets clipped
+const ORTHOGRAPHIC_DISTANCE: f32 = 50.0;
+// Seconds of the animated move to a recalled bookmark
+const TRANSITION_TIME: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    // WASD and mouse look, ESC releases the cursor
+    Fly,
+    // Right drag orbits, middle or Shift + right drag pans, the wheel zooms
+    Orbit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViewPreset {
+    Front,
+    Side,
+    Top,
+    Isometric,
+}
+
+impl ViewPreset {
+    pub const ALL: [ViewPreset; 4] = [
+        ViewPreset::Front,
+        ViewPreset::Side,
+        ViewPreset::Top,
+        ViewPreset::Isometric,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ViewPreset::Front => "Front",
+            ViewPreset::Side => "Side",
+            ViewPreset::Top => "Top",
+            ViewPreset::Isometric => "Iso",
+        }
+    }
+
+    // Yaw and pitch in radians, front looks along -Z and side along -X
+    pub fn angles(&self) -> (f32, f32) {
+        match self {
+            ViewPreset::Front => (0.0, 0.0),
+            ViewPreset::Side => (FRAC_PI_2, 0.0),
+            ViewPreset::Top => (0.0, FRAC_PI_2),
+            ViewPreset::Isometric => (std::f32::consts::FRAC_PI_4, (1.0 / 2.0_f32.sqrt()).atan()),
+        }
+    }
+}
+
+// Point of the active robot the camera moves along with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FollowTarget {
+    Tcp,
+    // Joint origin at the start of the link
+    Link(usize),
+}
+
+impl FollowTarget {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FollowTarget::Tcp => "TCP",
+            FollowTarget::Link(index) => JOINT_NAMES[*index],
+        }
+    }
+
+    fn point(&self, manipulability: &Manipulability) -> Vec3 {
+        match self {
+            FollowTarget::Tcp => manipulability.tcp.translation,
+            FollowTarget::Link(index) => manipulability.origins[*index],
+        }
+    }
+}
+
+pub struct CameraSettings {
+    pub mode: CameraMode,
+    pub orthographic: bool,
+    pub follow: Option<FollowTarget>,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            mode: CameraMode::Fly,
+            orthographic: false,
+            follow: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum CameraCommand {
+    Preset(ViewPreset),
+    FocusBase,
+    FocusTcp,
+    // Saves the current view under the given name
+    AddBookmark(String),
+    Recall(usize),
+}
+
+// Named orbit view stored in the scene file, angles in degrees
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CameraBookmark {
+    pub name: String,
+    pub focus: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub distance: f32,
+}
+
+impl CameraBookmark {
+    pub fn new(name: &str, orbit: &OrbitCamera) -> Self {
+        Self {
+            name: name.to_string(),
+            focus: orbit.focus,
+            yaw: orbit.yaw.to_degrees(),
+            pitch: orbit.pitch.to_degrees(),
+            distance: orbit.distance,
+        }
+    }
+
+    pub fn orbit(&self) -> OrbitCamera {
+        OrbitCamera {
+            focus: self.focus,
+            yaw: self.yaw.to_radians(),
+            pitch: self.pitch.to_radians(),
+            distance: self.distance,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CameraBookmarks {
+    pub list: Vec<CameraBookmark>,
+}
+
+// Animated move of an orbiting camera, removed when done
+#[derive(Component, Debug, Clone, Copy)]
+pub struct CameraTransition {
+    from: OrbitCamera,
+    to: OrbitCamera,
+    elapsed: f32,
+}
+
+// Camera position as angles and distance around a focus point, kept in sync while flying
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct OrbitCamera {
+    pub focus: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub distance: f32,
+}
+
+impl OrbitCamera {
+    // Orbit around the point `distance` in front of the camera
+    pub fn from_transform(transform: &Transform, distance: f32) -> Self {
+        let back = transform.back();
+        Self {
+            focus: transform.translation - back * distance,
+            yaw: back.x.atan2(back.z),
+            pitch: back.y.clamp(-1.0, 1.0).asin(),
+            distance,
+        }
+    }
+
+    pub fn rotation(&self) -> Quat {
+        Quat::from_rotation_y(self.yaw) * Quat::from_rotation_x(-self.pitch)
+    }
+
+    pub fn transform(&self, distance: f32) -> Transform {
+        let rotation = self.rotation();
+        Transform {
+            translation: self.focus + rotation * Vec3::Z * distance,
+            rotation,
+            ..Default::default()
+        }
+    }
+
+    // Eased in and out, the yaw turns the short way and the zoom changes evenly
+    pub fn lerp(&self, to: &OrbitCamera, t: f32) -> OrbitCamera {
+        let t = t * t * (3.0 - 2.0 * t);
+        let turn = (to.yaw - self.yaw + PI).rem_euclid(TAU) - PI;
+        OrbitCamera {
+            focus: self.focus.lerp(to.focus, t),
+            yaw: self.yaw + turn * t,
+            pitch: self.pitch + (to.pitch - self.pitch) * t,
+            distance: self.distance * (to.distance / self.distance).powf(t),
+        }
+    }
+
+    // Orthographic views are pulled back, only the scale zooms them
+    pub fn view_transform(&self, orthographic: bool) -> Transform {
+        if orthographic {
+            self.transform(self.distance.max(ORTHOGRAPHIC_DISTANCE))
+        } else {
+            self.transform(self.distance)
+        }
+    }
+
+    // Half height of the view at the focus, matches the perspective view when switching
+    pub fn orthographic_scale(&self) -> f32 {
+        self.distance * (PerspectiveProjection::default().fov / 2.0).tan()
+    }
+}
+
+pub fn orbit_camera(
+    mut egui_ctx: ResMut<EguiContext>,
+    settings: Res<CameraSettings>,
+    buttons: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    mut motion: EventReader<MouseMotion>,
+    mut wheel: EventReader<MouseWheel>,
+    mut cameras: Query<&mut OrbitCamera>,
+) {
+    let delta = motion
+        .iter()
+        .fold(Vec2::ZERO, |delta, event| delta + event.delta);
+    let scroll: f32 = wheel
+        .iter()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / 50.0,
+        })
+        .sum();
+    let ctx = egui_ctx.ctx_mut();
+    if settings.mode != CameraMode::Orbit || ctx.is_pointer_over_area() || ctx.wants_pointer_input()
+    {
+        return;
+    }
+    let shift = keys.pressed(KeyCode::LShift) || keys.pressed(KeyCode::RShift);
+    let pan =
+        buttons.pressed(MouseButton::Middle) || (shift && buttons.pressed(MouseButton::Right));
+    let orbit = !pan && buttons.pressed(MouseButton::Right);
+    for mut camera in cameras.iter_mut() {
+        if orbit && delta != Vec2::ZERO {
+            camera.yaw -= delta.x * ORBIT_SPEED;
+            camera.pitch = (camera.pitch + delta.y * ORBIT_SPEED).clamp(-FRAC_PI_2, FRAC_PI_2);
+        }
+        if pan && delta != Vec2::ZERO {
+            let rotation = camera.rotation();
+            let offset = (rotation * Vec3::X * -delta.x + rotation * Vec3::Y * delta.y)
+                * camera.distance
+                * PAN_SPEED;
+            camera.focus += offset;
+        }
+        if scroll != 0.0 {
+            camera.distance = (camera.distance * (1.0 - ZOOM_STEP).powf(scroll))
+                .clamp(MIN_DISTANCE, MAX_DISTANCE);
+        }
+    }
+}
+
+pub fn camera_commands(
+    mut commands: Commands,
+    mut events: EventReader<CameraCommand>,
+    mut settings: ResMut<CameraSettings>,
+    mut bookmarks: ResMut<CameraBookmarks>,
+    active: Res<ActiveRobot>,
+    robots: Query<(&GlobalTransform, &Manipulability), With<Robot>>,
+    mut cameras: Query<(Entity, &mut OrbitCamera)>,
+) {
+    let robot = active.entity.and_then(|robot| robots.get(robot).ok());
+    for command in events.iter() {
+        if let CameraCommand::AddBookmark(name) = command {
+            if let Some((_, camera)) = cameras.iter().next() {
+                bookmarks.list.push(CameraBookmark::new(name, camera));
+            }
+            continue;
+        }
+        settings.mode = CameraMode::Orbit;
+        for (entity, mut camera) in cameras.iter_mut() {
+            match command {
+                CameraCommand::Preset(preset) => {
+                    let (yaw, pitch) = preset.angles();
+                    camera.yaw = yaw;
+                    camera.pitch = pitch;
+                }
+                CameraCommand::FocusBase => {
+                    if let Some((base, _)) = robot {
+                        camera.focus = base.translation;
+                    }
+                }
+                CameraCommand::FocusTcp => {
+                    if let Some((_, manipulability)) = robot {
+                        camera.focus = manipulability.tcp.translation;
+                    }
+                }
+                CameraCommand::AddBookmark(_) => {}
+                CameraCommand::Recall(index) => {
+                    if let Some(bookmark) = bookmarks.list.get(*index) {
+                        commands.entity(entity).insert(CameraTransition {
+                            from: *camera,
+                            to: bookmark.orbit(),
+                            elapsed: 0.0,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn animate_camera(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut cameras: Query<(Entity, &mut OrbitCamera, &mut CameraTransition)>,
+) {
+    for (entity, mut camera, mut transition) in cameras.iter_mut() {
+        transition.elapsed += time.delta_seconds();
+        let t = (transition.elapsed / TRANSITION_TIME).min(1.0);
+        *camera = transition.from.lerp(&transition.to, t);
+        if t >= 1.0 {
+            commands.entity(entity).remove::<CameraTransition>();
+        }
+    }
+}
+
+// Moves the camera by as much as the followed point moved, keeping the view direction
+pub fn follow_camera(
+    settings: Res<CameraSettings>,
+    active: Res<ActiveRobot>,
+    robots: Query<&Manipulability>,
+    mut cameras: Query<(&mut OrbitCamera, &mut Transform), Without<CameraTransition>>,
+    mut last: Local<Option<(Entity, FollowTarget, Vec3)>>,
+) {
+    let followed = match (active.entity, settings.follow) {
+        (Some(robot), Some(target)) => robots
+            .get(robot)
+            .ok()
+            .map(|manipulability| (robot, target, target.point(manipulability))),
+        _ => None,
+    };
+    // Nothing moves on the first frame or after switching the robot or the target
+    if let (Some((robot, target, point)), Some((last_robot, last_target, last_point))) =
+        (followed, *last)
+    {
+        let offset = point - last_point;
+        if robot == last_robot && target == last_target && offset != Vec3::ZERO {
+            for (mut camera, mut transform) in cameras.iter_mut() {
+                match settings.mode {
+                    CameraMode::Orbit => camera.focus += offset,
+                    CameraMode::Fly => transform.translation += offset,
+                }
+            }
+        }
+    }
+    *last = followed;
+}
+
+// Places orbiting cameras and swaps their projection, the projection systems of bevy pick
+// up the inserted component and rebuild the projection matrix
+#[allow(clippy::type_complexity)]
+pub fn update_camera(
+    mut commands: Commands,
+    settings: Res<CameraSettings>,
+    mut cameras: Query<(
+        Entity,
+        &mut OrbitCamera,
+        &mut Transform,
+        &mut Camera,
+        Option<&mut OrthographicProjection>,
+    )>,
+) {
+    for (entity, mut orbit, mut transform, mut camera, orthographic) in cameras.iter_mut() {
+        match settings.mode {
+            CameraMode::Fly => {
+                let synced = OrbitCamera::from_transform(&transform, orbit.distance);
+                if *orbit != synced {
+                    *orbit = synced;
+                }
+            }
+            CameraMode::Orbit => {
+                let target = orbit.view_transform(settings.orthographic);
+                if *transform != target {
+                    *transform = target;
+                }
+            }
+        }
+        set_projection(
+            &mut commands,
+            entity,
+            &mut camera,
+            orthographic,
+            settings.orthographic,
+            orbit.orthographic_scale(),
+        );
+    }
+}
+
+// Swaps between perspective and orthographic, or only updates the orthographic scale
+pub fn set_projection(
+    commands: &mut Commands,
+    entity: Entity,
+    camera: &mut Camera,
+    current: Option<Mut<OrthographicProjection>>,
+    orthographic: bool,
+    scale: f32,
+) {
+    match (orthographic, current) {
+        (true, Some(mut projection)) => {
+            if projection.scale != scale {
+                projection.scale = scale;
+            }
+        }
+        (true, None) => {
+            let projection = OrthographicProjection {
+                scale,
+                scaling_mode: ScalingMode::FixedVertical,
+                depth_calculation: DepthCalculation::Distance,
+                ..Default::default()
+            };
+            camera.near = projection.near;
+            camera.far = projection.far;
+            commands
+                .entity(entity)
+                .remove::<PerspectiveProjection>()
+                .insert(projection);
+        }
+        (false, Some(_)) => {
+            let projection = PerspectiveProjection::default();
+            camera.near = projection.near;
+            camera.far = projection.far;
+            commands
+                .entity(entity)
+                .remove::<OrthographicProjection>()
+                .insert(projection);
+        }
+        (false, None) => {}
+    }
+}
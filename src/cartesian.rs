@@ -0,0 +1,152 @@
+use bevy::prelude::*;
+
+use crate::{
+    frames::Frames,
+    kinematics::{ik_step, Jacobian, Manipulability, JOINT_COUNT},
+    robot::{ActiveRobot, JointState},
+};
+
+const DAMPING: f32 = 0.05;
+// Orientation is only loosely held while translating, a 5 axis arm cannot keep all of it
+const ORIENTATION_WEIGHT: f32 = 0.1;
+const MOVE_TOLERANCE: f32 = 0.002;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JogFrame {
+    World,
+    Tool,
+    User,
+}
+
+impl JogFrame {
+    pub const ALL: [JogFrame; 3] = [JogFrame::World, JogFrame::Tool, JogFrame::User];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            JogFrame::World => "World",
+            JogFrame::Tool => "Tool",
+            JogFrame::User => "User",
+        }
+    }
+}
+
+// Requested TCP motion in the selected frame, directions are unit vectors set while a jog key is held
+pub struct CartesianJog {
+    pub frame: JogFrame,
+    pub linear: Vec3,
+    pub angular: Vec3,
+    // m/s and deg/s
+    pub speed: f32,
+    pub angular_speed: f32,
+}
+
+impl Default for CartesianJog {
+    fn default() -> Self {
+        Self {
+            frame: JogFrame::World,
+            linear: Vec3::ZERO,
+            angular: Vec3::ZERO,
+            speed: 0.3,
+            angular_speed: 30.0,
+        }
+    }
+}
+
+// Straight line move of the active robot's TCP to a position in the active user frame
+#[derive(Default)]
+pub struct CartesianMove {
+    pub target: Option<Vec3>,
+    // The last move got to its target, a cancelled one leaves this false
+    pub reached: bool,
+}
+
+pub fn frame_rotation(frame: JogFrame, frames: &Frames, tcp: &Transform) -> Quat {
+    match frame {
+        JogFrame::World => Quat::IDENTITY,
+        JogFrame::Tool => tcp.rotation,
+        JogFrame::User => frames.user_transform().rotation,
+    }
+}
+
+pub fn cartesian_motion(
+    time: Res<Time>,
+    jog: Res<CartesianJog>,
+    mut cartesian_move: ResMut<CartesianMove>,
+    frames: Res<Frames>,
+    active: Res<ActiveRobot>,
+    mut robots: Query<(&Manipulability, &mut JointState)>,
+) {
+    let (manipulability, mut joint_state) = match active.entity.map(|robot| robots.get_mut(robot)) {
+        Some(Ok(robot)) => robot,
+        _ => return,
+    };
+    let dt = time.delta_seconds();
+    let tcp = &manipulability.tcp;
+    let step = if jog.linear != Vec3::ZERO || jog.angular != Vec3::ZERO {
+        cartesian_move.target = None;
+        let rotation = frame_rotation(jog.frame, &frames, tcp);
+        twist_step(
+            &manipulability.jacobian,
+            rotation * jog.linear * jog.speed * dt,
+            rotation * jog.angular * jog.angular_speed.to_radians() * dt,
+        )
+    } else if let Some(target) = cartesian_move.target {
+        let target = frames.user_transform().mul_vec3(target);
+        match linear_step(
+            &manipulability.jacobian,
+            tcp.translation,
+            target,
+            jog.speed * dt,
+        ) {
+            Some(step) => step,
+            None => {
+                cartesian_move.target = None;
+                cartesian_move.reached = true;
+                return;
+            }
+        }
+    } else {
+        return;
+    };
+    joint_state.add(step);
+}
+
+// Joint step that moves the TCP by `linear` and turns it by `angular`
+fn twist_step(jacobian: &Jacobian, linear: Vec3, angular: Vec3) -> [f32; JOINT_COUNT] {
+    let orientation_weight = if angular == Vec3::ZERO {
+        ORIENTATION_WEIGHT
+    } else {
+        1.0
+    };
+    let twist = [
+        linear.x, linear.y, linear.z, angular.x, angular.y, angular.z,
+    ];
+    let weights = [
+        1.0,
+        1.0,
+        1.0,
+        orientation_weight,
+        orientation_weight,
+        orientation_weight,
+    ];
+    ik_step(jacobian, twist, weights, DAMPING)
+}
+
+// Joint step of a straight TCP move to `target` by at most `max_step` meters, None once it is
+// there. The ghost preview plans linear moves with it too.
+pub fn linear_step(
+    jacobian: &Jacobian,
+    tcp: Vec3,
+    target: Vec3,
+    max_step: f32,
+) -> Option<[f32; JOINT_COUNT]> {
+    let error = target - tcp;
+    if error.length() < MOVE_TOLERANCE {
+        return None;
+    }
+    Some(twist_step(
+        jacobian,
+        error.clamp_length_max(max_step),
+        Vec3::ZERO,
+    ))
+}
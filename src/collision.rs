@@ -0,0 +1,180 @@
+use bevy::prelude::*;
+
+use crate::{
+    kinematics::{Manipulability, JOINT_COUNT},
+    robot::Robot,
+};
+
+// Links are checked as capsules of this radius around the lines between joint origins
+pub const LINK_RADIUS: f32 = 0.15;
+
+// Pairs of robots whose links currently overlap, with the penetration depth
+#[derive(Default)]
+pub struct RobotCollisions {
+    pub pairs: Vec<(Entity, Entity, f32)>,
+}
+
+impl RobotCollisions {
+    pub fn involves(&self, robot: Entity) -> bool {
+        self.pairs
+            .iter()
+            .any(|(a, b, _)| *a == robot || *b == robot)
+    }
+}
+
+// Joint origins followed by the TCP, consecutive points form the link segments
+pub fn link_points(manipulability: &Manipulability) -> [Vec3; JOINT_COUNT + 1] {
+    let mut points = [manipulability.tcp.translation; JOINT_COUNT + 1];
+    points[..JOINT_COUNT].copy_from_slice(&manipulability.origins);
+    points
+}
+
+// Shortest distance between the segments p0-p1 and q0-q1
+pub fn segment_distance(p0: Vec3, p1: Vec3, q0: Vec3, q1: Vec3) -> f32 {
+    let d1 = p1 - p0;
+    let d2 = q1 - q0;
+    let r = p0 - q0;
+    let a = d1.length_squared();
+    let e = d2.length_squared();
+    let f = d2.dot(r);
+    let (s, t) = if a <= f32::EPSILON && e <= f32::EPSILON {
+        (0.0, 0.0)
+    } else if a <= f32::EPSILON {
+        (0.0, (f / e).clamp(0.0, 1.0))
+    } else {
+        let c = d1.dot(r);
+        if e <= f32::EPSILON {
+            ((-c / a).clamp(0.0, 1.0), 0.0)
+        } else {
+            let b = d1.dot(d2);
+            let denom = a * e - b * b;
+            // Parallel segments have no unique closest pair, any s works
+            let s = if denom > f32::EPSILON {
+                ((b * f - c * e) / denom).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let t = (b * s + f) / e;
+            if t < 0.0 {
+                ((-c / a).clamp(0.0, 1.0), 0.0)
+            } else if t > 1.0 {
+                (((b - c) / a).clamp(0.0, 1.0), 1.0)
+            } else {
+                (s, t)
+            }
+        }
+    };
+    (p0 + d1 * s).distance(q0 + d2 * t)
+}
+
+// Whether the segment p0-p1 passes through the axis aligned box
+pub fn segment_hits_box(p0: Vec3, p1: Vec3, min: Vec3, max: Vec3) -> bool {
+    let d = p1 - p0;
+    let (mut enter, mut exit) = (0.0f32, 1.0f32);
+    for axis in 0..3 {
+        if d[axis].abs() <= f32::EPSILON {
+            if p0[axis] < min[axis] || p0[axis] > max[axis] {
+                return false;
+            }
+            continue;
+        }
+        let a = (min[axis] - p0[axis]) / d[axis];
+        let b = (max[axis] - p0[axis]) / d[axis];
+        enter = enter.max(a.min(b));
+        exit = exit.min(a.max(b));
+        if enter > exit {
+            return false;
+        }
+    }
+    true
+}
+
+// Smallest distance between the link segments of two robots
+pub fn chain_distance(a: &[Vec3], b: &[Vec3]) -> f32 {
+    let mut distance = f32::INFINITY;
+    for p in a.windows(2) {
+        for q in b.windows(2) {
+            distance = distance.min(segment_distance(p[0], p[1], q[0], q[1]));
+        }
+    }
+    distance
+}
+
+pub fn check_robot_collisions(
+    robots: Query<(Entity, &Manipulability), With<Robot>>,
+    mut collisions: ResMut<RobotCollisions>,
+) {
+    let chains: Vec<_> = robots
+        .iter()
+        .map(|(robot, manipulability)| (robot, link_points(manipulability)))
+        .collect();
+    let mut pairs = Vec::new();
+    for (i, (a, a_points)) in chains.iter().enumerate() {
+        for (b, b_points) in chains.iter().skip(i + 1) {
+            let depth = 2.0 * LINK_RADIUS - chain_distance(a_points, b_points);
+            if depth > 0.0 {
+                pairs.push((*a, *b, depth));
+            }
+        }
+    }
+    collisions.pairs = pairs;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-5,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn parallel_segments() {
+        // Overlapping along X, 0.5 apart
+        let distance = segment_distance(
+            Vec3::ZERO,
+            Vec3::X * 2.0,
+            Vec3::new(1.0, 0.5, 0.0),
+            Vec3::new(3.0, 0.5, 0.0),
+        );
+        assert_close(distance, 0.5);
+        // End to end on the same line, the gap between the ends counts
+        let distance = segment_distance(Vec3::ZERO, Vec3::X, Vec3::X * 3.0, Vec3::X * 2.0);
+        assert_close(distance, 1.0);
+    }
+
+    #[test]
+    fn crossing_segments() {
+        let distance = segment_distance(-Vec3::X, Vec3::X, -Vec3::Z, Vec3::Z);
+        assert_close(distance, 0.0);
+        // Crossing when seen from above, one passes 0.3 over the other
+        let distance = segment_distance(
+            -Vec3::X,
+            Vec3::X,
+            Vec3::new(0.0, 0.3, -1.0),
+            Vec3::new(0.0, 0.3, 1.0),
+        );
+        assert_close(distance, 0.3);
+    }
+
+    #[test]
+    fn degenerate_segments() {
+        let point = Vec3::new(0.5, 2.0, 0.0);
+        // A point and a segment, either way round
+        assert_close(segment_distance(point, point, Vec3::ZERO, Vec3::X), 2.0);
+        assert_close(segment_distance(Vec3::ZERO, Vec3::X, point, point), 2.0);
+        // Past the end of the segment the end point is closest
+        let beyond = Vec3::new(4.0, 0.0, 0.0);
+        assert_close(segment_distance(beyond, beyond, Vec3::ZERO, Vec3::X), 3.0);
+        // Two points
+        assert_close(
+            segment_distance(point, point, Vec3::ZERO, Vec3::ZERO),
+            point.length(),
+        );
+    }
+}
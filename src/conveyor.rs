@@ -0,0 +1,181 @@
+use bevy::prelude::*;
+
+use crate::tool::Graspable;
+
+const PART_SIZE: f32 = 0.15;
+const BELT_THICKNESS: f32 = 0.1;
+
+// Belt that carries parts along its local X axis
+#[derive(Component, Debug)]
+pub struct Conveyor {
+    pub speed: f32,
+    pub length: f32,
+    pub width: f32,
+}
+
+// Emits a new part on the belt every time the timer finishes
+#[derive(Component, Debug)]
+pub struct PartSpawner {
+    pub enabled: bool,
+    pub timer: Timer,
+}
+
+// Despawns parts that reach it
+#[derive(Component, Debug)]
+pub struct PartSink {
+    pub radius: f32,
+}
+
+#[derive(Component, Debug)]
+pub struct Part;
+
+// Reports whether any part is within `radius`, programs can wait on it by name
+#[derive(Component, Debug)]
+pub struct PresenceSensor {
+    pub name: String,
+    pub radius: f32,
+    pub active: bool,
+}
+
+// Parts lying free in the scene, grasped parts are children of the tool
+type LoosePart = (With<Part>, Without<Parent>);
+
+pub struct PartAssets {
+    pub mesh: Handle<Mesh>,
+    pub material: Handle<StandardMaterial>,
+}
+
+pub fn setup_conveyor(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let conveyor = Conveyor {
+        speed: 0.2,
+        length: 4.0,
+        width: 0.5,
+    };
+    let top = BELT_THICKNESS / 2.0;
+    let half_length = conveyor.length / 2.0;
+    // Behind the robot, in reach when the shoulder is turned around
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Box::new(
+                conveyor.length,
+                BELT_THICKNESS,
+                conveyor.width,
+            ))),
+            material: materials.add(Color::rgb(0.15, 0.15, 0.15).into()),
+            transform: Transform::from_xyz(0.0, 0.6, -2.7),
+            ..Default::default()
+        })
+        .insert(conveyor)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TransformBundle::from_transform(Transform::from_xyz(
+                    -half_length + PART_SIZE,
+                    top + PART_SIZE / 2.0,
+                    0.0,
+                )))
+                .insert(PartSpawner {
+                    enabled: true,
+                    timer: Timer::from_seconds(4.0, true),
+                });
+            parent
+                .spawn_bundle(TransformBundle::from_transform(Transform::from_xyz(
+                    half_length,
+                    top + PART_SIZE / 2.0,
+                    0.0,
+                )))
+                .insert(PartSink { radius: PART_SIZE });
+            parent
+                .spawn_bundle(TransformBundle::from_transform(Transform::from_xyz(
+                    0.0,
+                    top + PART_SIZE / 2.0,
+                    0.0,
+                )))
+                .insert(PresenceSensor {
+                    name: "pick".to_string(),
+                    radius: PART_SIZE,
+                    active: false,
+                });
+        });
+
+    commands.insert_resource(PartAssets {
+        mesh: meshes.add(Mesh::from(shape::Cube { size: PART_SIZE })),
+        material: materials.add(Color::rgb(0.9, 0.5, 0.1).into()),
+    });
+}
+
+pub fn spawn_parts(
+    mut commands: Commands,
+    time: Res<Time>,
+    assets: Res<PartAssets>,
+    mut spawners: Query<(&mut PartSpawner, &GlobalTransform)>,
+) {
+    for (mut spawner, transform) in spawners.iter_mut() {
+        if !spawner.enabled {
+            continue;
+        }
+        if spawner.timer.tick(time.delta()).just_finished() {
+            commands
+                .spawn_bundle(PbrBundle {
+                    mesh: assets.mesh.clone(),
+                    material: assets.material.clone(),
+                    transform: Transform::from_translation(transform.translation),
+                    ..Default::default()
+                })
+                .insert(Part)
+                .insert(Graspable);
+        }
+    }
+}
+
+pub fn move_parts(
+    time: Res<Time>,
+    conveyors: Query<(&Conveyor, &GlobalTransform)>,
+    mut parts: Query<&mut Transform, LoosePart>,
+) {
+    for (conveyor, belt) in conveyors.iter() {
+        let to_belt = belt.compute_matrix().inverse();
+        let direction = belt.rotation * Vec3::X;
+        for mut transform in parts.iter_mut() {
+            let local = to_belt.transform_point3(transform.translation);
+            let on_belt = local.x.abs() <= conveyor.length / 2.0
+                && local.z.abs() <= conveyor.width / 2.0
+                && local.y >= 0.0
+                && local.y <= BELT_THICKNESS / 2.0 + PART_SIZE;
+            if on_belt {
+                transform.translation += direction * conveyor.speed * time.delta_seconds();
+            }
+        }
+    }
+}
+
+pub fn sink_parts(
+    mut commands: Commands,
+    sinks: Query<(&PartSink, &GlobalTransform)>,
+    parts: Query<(Entity, &GlobalTransform), LoosePart>,
+) {
+    for (sink, sink_transform) in sinks.iter() {
+        for (part, transform) in parts.iter() {
+            if transform.translation.distance(sink_transform.translation) < sink.radius {
+                commands.entity(part).despawn_recursive();
+            }
+        }
+    }
+}
+
+pub fn update_sensors(
+    mut sensors: Query<(&mut PresenceSensor, &GlobalTransform)>,
+    parts: Query<&GlobalTransform, With<Part>>,
+) {
+    for (mut sensor, sensor_transform) in sensors.iter_mut() {
+        let active = parts.iter().any(|transform| {
+            transform.translation.distance(sensor_transform.translation) < sensor.radius
+        });
+        if sensor.active != active {
+            sensor.active = active;
+        }
+    }
+}
@@ -0,0 +1,585 @@
+use std::collections::VecDeque;
+
+use bevy::{math::const_vec3, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    kinematics::{solve, Manipulability, JOINT_COUNT},
+    robot::{Joint, JointState, Robot, FLANGE_OFFSET},
+    servo::ServoControl,
+};
+
+pub const GRAVITY: Vec3 = const_vec3!([0.0, -9.81, 0.0]);
+
+// Seconds of torque history kept for the plots
+const HISTORY_SECONDS: f64 = 10.0;
+// Weight of the newest finite difference, smooths out frame time jitter
+const MOTION_FILTER: f32 = 0.3;
+
+// Mass properties of the link moved by a joint, in that joint's link frame
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LinkDynamics {
+    // kg
+    pub mass: f32,
+    pub center_of_mass: Vec3,
+    // Principal moments about the center of mass along the link axes, kg m²
+    pub inertia: Vec3,
+}
+
+// What the wrist is rated for, the payload has to stay inside all three
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LoadDiagram {
+    // kg
+    pub max_mass: f32,
+    // Static moment of the payload about the flange, Nm
+    pub max_moment: f32,
+    // Payload inertia about the wrist axis, kg m²
+    pub max_inertia: f32,
+}
+
+impl LoadDiagram {
+    pub fn violations(&self, payload: &Payload, load: &PayloadLoad) -> Vec<String> {
+        let mut violations = Vec::new();
+        if payload.mass > self.max_mass {
+            violations.push(format!(
+                "payload {:.1} kg over rated {:.1} kg",
+                payload.mass, self.max_mass
+            ));
+        }
+        if load.moment > self.max_moment {
+            violations.push(format!(
+                "payload moment {:.1} Nm over rated {:.1} Nm",
+                load.moment, self.max_moment
+            ));
+        }
+        if load.inertia > self.max_inertia {
+            violations.push(format!(
+                "payload inertia {:.2} kg m² over rated {:.2} kg m²",
+                load.inertia, self.max_inertia
+            ));
+        }
+        violations
+    }
+}
+
+// Link masses and motor ratings of a robot, part of its description in the scene file
+#[derive(Component, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RobotDynamics {
+    pub links: [LinkDynamics; JOINT_COUNT],
+    // Continuous torque of each joint motor after the gearbox, Nm
+    pub motor_limits: [f32; JOINT_COUNT],
+    // Joint and gearbox stiffness, Nm/rad
+    pub stiffness: [f32; JOINT_COUNT],
+    pub rated_load: LoadDiagram,
+}
+
+impl Default for RobotDynamics {
+    fn default() -> Self {
+        // Centers of mass sit halfway to the next joint
+        Self {
+            links: [
+                LinkDynamics {
+                    mass: 40.0,
+                    center_of_mass: Vec3::new(0.0, 0.4, 0.1),
+                    inertia: Vec3::new(2.0, 1.5, 2.0),
+                },
+                LinkDynamics {
+                    mass: 25.0,
+                    center_of_mass: Vec3::new(0.0, 0.369, -0.75),
+                    inertia: Vec3::new(5.8, 4.7, 1.2),
+                },
+                LinkDynamics {
+                    mass: 20.0,
+                    center_of_mass: Vec3::new(0.0, 0.455, 0.8875),
+                    inertia: Vec3::new(5.0, 3.8, 1.4),
+                },
+                LinkDynamics {
+                    mass: 10.0,
+                    center_of_mass: Vec3::new(0.0, -0.046, 0.168),
+                    inertia: Vec3::new(0.12, 0.12, 0.05),
+                },
+                LinkDynamics {
+                    mass: 5.0,
+                    center_of_mass: Vec3::new(0.0, 0.0, 0.325),
+                    inertia: Vec3::new(0.19, 0.19, 0.02),
+                },
+            ],
+            motor_limits: [900.0, 1800.0, 1000.0, 250.0, 60.0],
+            stiffness: [2.0e6, 3.0e6, 2.0e6, 5.0e5, 2.0e5],
+            rated_load: LoadDiagram {
+                max_mass: 10.0,
+                max_moment: 30.0,
+                max_inertia: 1.0,
+            },
+        }
+    }
+}
+
+// Load carried on the flange, the center of mass is in the flange frame
+#[derive(Component, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Payload {
+    pub mass: f32,
+    pub center_of_mass: Vec3,
+    pub inertia: Vec3,
+}
+
+impl Default for Payload {
+    fn default() -> Self {
+        Self {
+            mass: 0.0,
+            center_of_mass: Vec3::new(0.0, 0.0, 0.1),
+            inertia: Vec3::ZERO,
+        }
+    }
+}
+
+impl Payload {
+    pub fn link(&self) -> LinkDynamics {
+        LinkDynamics {
+            mass: self.mass,
+            center_of_mass: self.center_of_mass,
+            inertia: self.inertia,
+        }
+    }
+}
+
+// How hard the payload loads the wrist at the current pose, and how far the TCP sags
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct PayloadLoad {
+    pub moment: f32,
+    pub inertia: f32,
+    // TCP displacement from joint compliance under the current torques, m
+    pub deflection: f32,
+}
+
+// Link mass properties in world coordinates at the current pose
+#[derive(Debug, Clone, Copy)]
+pub struct LinkState {
+    pub mass: f32,
+    pub center_of_mass: Vec3,
+    pub inertia: Mat3,
+}
+
+impl LinkState {
+    pub fn new(link: &LinkDynamics, transform: &GlobalTransform) -> Self {
+        let rotation = Mat3::from_quat(transform.rotation);
+        Self {
+            mass: link.mass,
+            center_of_mass: transform.mul_vec3(link.center_of_mass),
+            inertia: rotation * Mat3::from_diagonal(link.inertia) * rotation.transpose(),
+        }
+    }
+
+    // Both bodies as one rigid body, inertias moved to the common center of mass
+    pub fn combine(&self, other: &LinkState) -> LinkState {
+        let mass = self.mass + other.mass;
+        if mass <= f32::EPSILON {
+            return *self;
+        }
+        let center_of_mass =
+            (self.center_of_mass * self.mass + other.center_of_mass * other.mass) / mass;
+        LinkState {
+            mass,
+            center_of_mass,
+            inertia: self.inertia_about(center_of_mass) + other.inertia_about(center_of_mass),
+        }
+    }
+
+    // Parallel axis theorem
+    pub fn inertia_about(&self, point: Vec3) -> Mat3 {
+        let d = self.center_of_mass - point;
+        let outer = Mat3::from_cols(d * d.x, d * d.y, d * d.z);
+        self.inertia + (Mat3::from_diagonal(Vec3::splat(d.length_squared())) - outer) * self.mass
+    }
+}
+
+// Joint velocities and accelerations estimated from the joint angles, rad/s and rad/s²
+#[derive(Component, Debug, Clone, Default)]
+pub struct JointMotion {
+    pub previous: Option<[f32; JOINT_COUNT]>,
+    pub velocity: [f32; JOINT_COUNT],
+    pub acceleration: [f32; JOINT_COUNT],
+}
+
+#[derive(Component, Debug, Clone, Default)]
+pub struct JointTorques {
+    pub current: [f32; JOINT_COUNT],
+    // Seconds since startup and the torques at that time
+    pub history: VecDeque<(f64, [f32; JOINT_COUNT])>,
+}
+
+impl JointTorques {
+    pub fn overloaded(&self, limits: &[f32; JOINT_COUNT]) -> Vec<usize> {
+        (0..JOINT_COUNT)
+            .filter(|index| self.current[*index].abs() > limits[*index])
+            .collect()
+    }
+}
+
+// Recursive Newton-Euler in world coordinates. Gravity enters as an upward acceleration of the base.
+pub fn inverse_dynamics(
+    origins: &[Vec3; JOINT_COUNT],
+    axes: &[Vec3; JOINT_COUNT],
+    links: &[LinkState; JOINT_COUNT],
+    velocity: &[f32; JOINT_COUNT],
+    acceleration: &[f32; JOINT_COUNT],
+    gravity: Vec3,
+) -> [f32; JOINT_COUNT] {
+    let mut forces = [Vec3::ZERO; JOINT_COUNT];
+    let mut moments = [Vec3::ZERO; JOINT_COUNT];
+
+    // Outward pass: velocities and accelerations of each link
+    let mut omega = Vec3::ZERO;
+    let mut alpha = Vec3::ZERO;
+    let mut origin_acceleration = -gravity;
+    let mut previous_origin = origins[0];
+    for i in 0..JOINT_COUNT {
+        let r = origins[i] - previous_origin;
+        origin_acceleration += alpha.cross(r) + omega.cross(omega.cross(r));
+        let joint_rate = axes[i] * velocity[i];
+        alpha += axes[i] * acceleration[i] + omega.cross(joint_rate);
+        omega += joint_rate;
+
+        let link = &links[i];
+        let c = link.center_of_mass - origins[i];
+        let com_acceleration = origin_acceleration + alpha.cross(c) + omega.cross(omega.cross(c));
+        forces[i] = link.mass * com_acceleration;
+        moments[i] = link.inertia * alpha + omega.cross(link.inertia * omega);
+        previous_origin = origins[i];
+    }
+
+    // Inward pass: forces and moments each joint has to carry
+    let mut torques = [0.0; JOINT_COUNT];
+    let mut force = Vec3::ZERO;
+    let mut moment = Vec3::ZERO;
+    for i in (0..JOINT_COUNT).rev() {
+        let child_force = force;
+        let child_moment = moment;
+        let child_origin = origins.get(i + 1).copied().unwrap_or(origins[i]);
+        force = forces[i] + child_force;
+        moment = moments[i]
+            + (links[i].center_of_mass - origins[i]).cross(forces[i])
+            + child_moment
+            + (child_origin - origins[i]).cross(child_force);
+        torques[i] = moment.dot(axes[i]);
+    }
+    torques
+}
+
+// Joint space inertia matrix, column j holds the torques for a unit acceleration of joint j
+pub fn mass_matrix(
+    origins: &[Vec3; JOINT_COUNT],
+    axes: &[Vec3; JOINT_COUNT],
+    links: &[LinkState; JOINT_COUNT],
+) -> [[f32; JOINT_COUNT]; JOINT_COUNT] {
+    let mut matrix = [[0.0; JOINT_COUNT]; JOINT_COUNT];
+    for column in 0..JOINT_COUNT {
+        let mut acceleration = [0.0; JOINT_COUNT];
+        acceleration[column] = 1.0;
+        let torques = inverse_dynamics(
+            origins,
+            axes,
+            links,
+            &[0.0; JOINT_COUNT],
+            &acceleration,
+            Vec3::ZERO,
+        );
+        for (row, torque) in matrix.iter_mut().zip(torques) {
+            row[column] = torque;
+        }
+    }
+    matrix
+}
+
+// Joint accelerations produced by the given torques, M(q) q'' = tau - C(q, q') q' - g(q)
+pub fn forward_dynamics(
+    origins: &[Vec3; JOINT_COUNT],
+    axes: &[Vec3; JOINT_COUNT],
+    links: &[LinkState; JOINT_COUNT],
+    velocity: &[f32; JOINT_COUNT],
+    torques: &[f32; JOINT_COUNT],
+    gravity: Vec3,
+) -> [f32; JOINT_COUNT] {
+    let bias = inverse_dynamics(origins, axes, links, velocity, &[0.0; JOINT_COUNT], gravity);
+    let mut rhs = *torques;
+    for (value, bias) in rhs.iter_mut().zip(bias) {
+        *value -= bias;
+    }
+    solve(mass_matrix(origins, axes, links), rhs)
+}
+
+pub fn flange_transform(wrist: &GlobalTransform) -> GlobalTransform {
+    wrist.mul_transform(Transform::from_translation(FLANGE_OFFSET))
+}
+
+// Mass properties of every link at the given link transforms, the payload is merged into the wrist
+pub fn chain_links(
+    dynamics: &RobotDynamics,
+    payload: &Payload,
+    transforms: &[GlobalTransform; JOINT_COUNT],
+) -> [LinkState; JOINT_COUNT] {
+    let mut links = [LinkState {
+        mass: 0.0,
+        center_of_mass: Vec3::ZERO,
+        inertia: Mat3::ZERO,
+    }; JOINT_COUNT];
+    for ((state, link), transform) in links.iter_mut().zip(&dynamics.links).zip(transforms) {
+        *state = LinkState::new(link, transform);
+    }
+    let flange = flange_transform(&transforms[JOINT_COUNT - 1]);
+    let wrist = &mut links[JOINT_COUNT - 1];
+    *wrist = wrist.combine(&LinkState::new(&payload.link(), &flange));
+    links
+}
+
+pub fn update_joint_motion(
+    time: Res<Time>,
+    mut robots: Query<(&JointState, &ServoControl, &mut JointMotion)>,
+) {
+    let dt = time.delta_seconds();
+    if dt <= f32::EPSILON {
+        return;
+    }
+    for (state, servo, mut motion) in robots.iter_mut() {
+        let angles = servo.angles(state);
+        let motion = &mut *motion;
+        if let Some(previous) = motion.previous {
+            let rates = motion
+                .velocity
+                .iter_mut()
+                .zip(motion.acceleration.iter_mut());
+            for ((angle, previous), (velocity, acceleration)) in
+                angles.iter().zip(previous).zip(rates)
+            {
+                let new_velocity = (angle - previous) / dt;
+                let new_acceleration = (new_velocity - *velocity) / dt;
+                *velocity += MOTION_FILTER * (new_velocity - *velocity);
+                *acceleration += MOTION_FILTER * (new_acceleration - *acceleration);
+            }
+        }
+        motion.previous = Some(angles);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn compute_torques(
+    time: Res<Time>,
+    joints: Query<(&Joint, &GlobalTransform)>,
+    mut robots: Query<
+        (
+            Entity,
+            &Manipulability,
+            &RobotDynamics,
+            &Payload,
+            &JointMotion,
+            &mut JointTorques,
+            &mut PayloadLoad,
+        ),
+        With<Robot>,
+    >,
+) {
+    let now = time.seconds_since_startup();
+    for (robot, manipulability, dynamics, payload, motion, mut torques, mut load) in
+        robots.iter_mut()
+    {
+        let mut transforms = [GlobalTransform::identity(); JOINT_COUNT];
+        for (joint, transform) in joints.iter().filter(|(joint, _)| joint.robot == robot) {
+            transforms[joint.index] = *transform;
+        }
+        let links = chain_links(dynamics, payload, &transforms);
+
+        let flange = flange_transform(&transforms[JOINT_COUNT - 1]);
+        let payload_state = LinkState::new(&payload.link(), &flange);
+        let arm = payload_state.center_of_mass - flange.translation;
+        let axis = manipulability.axes[JOINT_COUNT - 1];
+        load.moment = arm.cross(GRAVITY * payload.mass).length();
+        load.inertia = axis.dot(payload_state.inertia_about(flange.translation) * axis);
+
+        let current = inverse_dynamics(
+            &manipulability.origins,
+            &manipulability.axes,
+            &links,
+            &motion.velocity,
+            &motion.acceleration,
+            GRAVITY,
+        );
+        let mut deflection = Vec3::ZERO;
+        for (index, (torque, stiffness)) in current.iter().zip(dynamics.stiffness).enumerate() {
+            let column = Vec3::new(
+                manipulability.jacobian[0][index],
+                manipulability.jacobian[1][index],
+                manipulability.jacobian[2][index],
+            );
+            deflection += column * *torque / stiffness;
+        }
+        load.deflection = deflection.length();
+
+        torques.current = current;
+        torques.history.push_back((now, current));
+        while matches!(torques.history.front(), Some((time, _)) if now - time > HISTORY_SECONDS) {
+            torques.history.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bent arm with no two axes in line, link masses as in the default robot
+    fn test_chain() -> (
+        [Vec3; JOINT_COUNT],
+        [Vec3; JOINT_COUNT],
+        [LinkState; JOINT_COUNT],
+    ) {
+        let origins = [
+            Vec3::new(0.0, 0.5, 0.0),
+            Vec3::new(0.1, 0.8, 0.2),
+            Vec3::new(0.3, 1.9, -0.4),
+            Vec3::new(0.9, 2.2, 0.3),
+            Vec3::new(1.1, 2.1, 0.5),
+        ];
+        let axes = [
+            Vec3::Y,
+            Vec3::X,
+            Vec3::new(0.6, 0.0, 0.8),
+            Vec3::new(0.0, 0.8, -0.6),
+            Vec3::Z,
+        ];
+        let link = |mass: f32, center_of_mass: Vec3, inertia: Vec3| LinkState {
+            mass,
+            center_of_mass,
+            inertia: Mat3::from_diagonal(inertia),
+        };
+        let links = [
+            link(40.0, Vec3::new(0.0, 0.6, 0.1), Vec3::new(2.0, 1.5, 2.0)),
+            link(25.0, Vec3::new(0.2, 1.3, -0.1), Vec3::new(5.8, 4.7, 1.2)),
+            link(20.0, Vec3::new(0.6, 2.0, 0.0), Vec3::new(5.0, 3.8, 1.4)),
+            link(10.0, Vec3::new(1.0, 2.2, 0.4), Vec3::new(0.12, 0.12, 0.05)),
+            link(5.0, Vec3::new(1.2, 2.0, 0.7), Vec3::new(0.19, 0.19, 0.02)),
+        ];
+        (origins, axes, links)
+    }
+
+    #[test]
+    fn static_torques_hold_the_links_against_gravity() {
+        let (origins, axes, links) = test_chain();
+        let zero = [0.0; JOINT_COUNT];
+        let torques = inverse_dynamics(&origins, &axes, &links, &zero, &zero, GRAVITY);
+        for joint in 0..JOINT_COUNT {
+            // Moment of the weights of this link and all after it about the joint axis
+            let expected: f32 = links[joint..]
+                .iter()
+                .map(|link| {
+                    (link.center_of_mass - origins[joint])
+                        .cross(-GRAVITY * link.mass)
+                        .dot(axes[joint])
+                })
+                .sum();
+            assert!(
+                (torques[joint] - expected).abs() < 1e-3 * expected.abs().max(1.0),
+                "joint {}: {} != {}",
+                joint,
+                torques[joint],
+                expected
+            );
+        }
+        // The base turns about the vertical, gravity does not load it
+        assert!(torques[0].abs() < 1e-3);
+    }
+
+    #[test]
+    fn accelerating_a_joint_needs_its_inertia_torque() {
+        let (origins, axes, mut links) = test_chain();
+        for link in links[1..].iter_mut() {
+            link.mass = 0.0;
+            link.inertia = Mat3::ZERO;
+        }
+        let mut acceleration = [0.0; JOINT_COUNT];
+        acceleration[0] = 2.0;
+        let torques = inverse_dynamics(
+            &origins,
+            &axes,
+            &links,
+            &[0.0; JOINT_COUNT],
+            &acceleration,
+            Vec3::ZERO,
+        );
+        // I about the axis plus m r² of the center of mass
+        let link = &links[0];
+        let offset = link.center_of_mass - origins[0];
+        let radius = offset - axes[0] * offset.dot(axes[0]);
+        let inertia = axes[0].dot(link.inertia * axes[0]) + link.mass * radius.length_squared();
+        assert!((torques[0] - inertia * 2.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn load_diagram_reports_each_exceeded_rating() {
+        let rated = RobotDynamics::default().rated_load;
+        let light = Payload {
+            mass: 2.0,
+            ..Default::default()
+        };
+        let within = PayloadLoad {
+            moment: 5.0,
+            inertia: 0.1,
+            ..Default::default()
+        };
+        assert!(rated.violations(&light, &within).is_empty());
+
+        let heavy = Payload {
+            mass: rated.max_mass + 1.0,
+            ..Default::default()
+        };
+        let over = PayloadLoad {
+            moment: rated.max_moment + 1.0,
+            inertia: rated.max_inertia + 1.0,
+            ..Default::default()
+        };
+        assert_eq!(rated.violations(&heavy, &within).len(), 1);
+        assert_eq!(rated.violations(&light, &over).len(), 2);
+        assert_eq!(rated.violations(&heavy, &over).len(), 3);
+    }
+
+    #[test]
+    fn mass_matrix_is_symmetric() {
+        let (origins, axes, links) = test_chain();
+        let matrix = mass_matrix(&origins, &axes, &links);
+        for i in 0..JOINT_COUNT {
+            assert!(matrix[i][i] > 0.0, "diagonal {} is {}", i, matrix[i][i]);
+            for j in 0..i {
+                let tolerance = 1e-4 * matrix[i][i].max(matrix[j][j]);
+                assert!(
+                    (matrix[i][j] - matrix[j][i]).abs() < tolerance,
+                    "[{}][{}] {} != [{}][{}] {}",
+                    i,
+                    j,
+                    matrix[i][j],
+                    j,
+                    i,
+                    matrix[j][i]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn forward_dynamics_inverts_inverse_dynamics() {
+        let (origins, axes, links) = test_chain();
+        let velocity = [0.5, -1.0, 0.8, 1.5, -2.0];
+        let torques = [120.0, -800.0, 300.0, 40.0, -5.0];
+        let acceleration = forward_dynamics(&origins, &axes, &links, &velocity, &torques, GRAVITY);
+        let round_trip =
+            inverse_dynamics(&origins, &axes, &links, &velocity, &acceleration, GRAVITY);
+        for (joint, (expected, actual)) in torques.iter().zip(round_trip).enumerate() {
+            assert!(
+                (expected - actual).abs() < 1e-3 * expected.abs().max(1.0),
+                "joint {}: {} != {}",
+                joint,
+                expected,
+                actual
+            );
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::{error::Error, fs};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const FRAMES_FILE: &str = "frames.ron";
+
+// Named coordinate frame, rotation is XYZ Euler angles in degrees
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    pub name: String,
+    pub translation: Vec3,
+    pub rotation: Vec3,
+}
+
+impl Frame {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            translation: Vec3::ZERO,
+            rotation: Vec3::ZERO,
+        }
+    }
+
+    pub fn transform(&self) -> Transform {
+        Transform {
+            translation: self.translation,
+            rotation: Quat::from_euler(
+                EulerRot::XYZ,
+                self.rotation.x.to_radians(),
+                self.rotation.y.to_radians(),
+                self.rotation.z.to_radians(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    pub fn set_transform(&mut self, transform: &Transform) {
+        let (x, y, z) = transform.rotation.to_euler(EulerRot::XYZ);
+        self.translation = transform.translation;
+        self.rotation = Vec3::new(x.to_degrees(), y.to_degrees(), z.to_degrees());
+    }
+}
+
+// Tool frames are TCP offsets from the flange, user frames are work objects in the world.
+// The first entry of each list is built in: the mounted tool's own TCP and the world frame.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Frames {
+    pub tool: Vec<Frame>,
+    pub user: Vec<Frame>,
+    pub active_tool: usize,
+    pub active_user: usize,
+}
+
+impl Default for Frames {
+    fn default() -> Self {
+        Self {
+            tool: vec![Frame::new("tool0")],
+            user: vec![Frame::new("world")],
+            active_tool: 0,
+            active_user: 0,
+        }
+    }
+}
+
+impl Frames {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let mut frames: Self = ron::from_str(&fs::read_to_string(path)?)?;
+        frames.validate();
+        Ok(frames)
+    }
+
+    // Hand-edited files may lack the built-in frames or point past the end of a list
+    pub fn validate(&mut self) {
+        let default = Self::default();
+        if self.tool.is_empty() {
+            self.tool = default.tool;
+        }
+        if self.user.is_empty() {
+            self.user = default.user;
+        }
+        self.active_tool = self.active_tool.min(self.tool.len() - 1);
+        self.active_user = self.active_user.min(self.user.len() - 1);
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    // None when the mounted tool's own TCP is used
+    pub fn tool_offset(&self) -> Option<Transform> {
+        match self.active_tool {
+            0 => None,
+            index => self.tool.get(index).map(Frame::transform),
+        }
+    }
+
+    pub fn user_transform(&self) -> Transform {
+        self.user
+            .get(self.active_user)
+            .map_or(Transform::identity(), Frame::transform)
+    }
+
+    // TCP pose expressed in the active user frame
+    pub fn to_user(&self, tcp: &Transform) -> Transform {
+        let user = self.user_transform();
+        Transform::from_matrix(user.compute_matrix().inverse() * tcp.compute_matrix())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_restores_empty_lists() {
+        let mut frames = Frames {
+            tool: Vec::new(),
+            user: Vec::new(),
+            active_tool: 3,
+            active_user: 2,
+        };
+        frames.validate();
+        assert_eq!(frames.tool.len(), 1);
+        assert_eq!(frames.user.len(), 1);
+        assert_eq!((frames.active_tool, frames.active_user), (0, 0));
+    }
+
+    #[test]
+    fn validate_clamps_active_frames() {
+        let mut frames = Frames::default();
+        frames.user.push(Frame::new("table"));
+        frames.active_user = 5;
+        frames.active_tool = 1;
+        frames.validate();
+        assert_eq!(frames.active_user, 1);
+        assert_eq!(frames.active_tool, 0);
+
+        let mut frames = Frames::default();
+        frames.user.push(Frame::new("table"));
+        frames.active_user = 1;
+        frames.validate();
+        assert_eq!(frames.active_user, 1);
+    }
+}
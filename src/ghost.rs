@@ -0,0 +1,204 @@
+use bevy::{pbr::NotShadowCaster, prelude::*};
+
+use crate::{
+    cartesian::linear_step,
+    dynamics::flange_transform,
+    frames::Frames,
+    kinematics::{
+        chain_axes, geometric_jacobian, joint_frames, link_transforms, JointFrame, Manipulability,
+        JOINT_COUNT,
+    },
+    robot::{Joint, JointState, Robot},
+    servo::ServoControl,
+};
+
+// Ghosts drawn along a planned path, not counting the one at the target
+const SNAPSHOTS: usize = 3;
+// Step (m) of the simulated linear move, about what cartesian_motion moves per frame
+const LINEAR_STEP: f32 = 0.005;
+const MAX_LINEAR_STEPS: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GhostTarget {
+    // Joint move in degrees, from the given pose or else the current one
+    Joints {
+        from: Option<[f32; JOINT_COUNT]>,
+        to: [f32; JOINT_COUNT],
+    },
+    // Straight TCP move to a position in the active user frame
+    Linear(Vec3),
+}
+
+// What the side panel wants previewed, set every frame
+#[derive(Debug, Default)]
+pub struct GhostPreview {
+    pub robot: Option<Entity>,
+    pub target: Option<GhostTarget>,
+}
+
+#[derive(Component, Debug)]
+pub struct Ghost;
+
+// Robot, target, its current angles and base pose the shown ghosts were planned for
+type GhostRequest = (Entity, GhostTarget, [f32; JOINT_COUNT], GlobalTransform);
+
+pub struct GhostMaterials {
+    target: Handle<StandardMaterial>,
+    path: Handle<StandardMaterial>,
+}
+
+impl FromWorld for GhostMaterials {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        let mut ghost = |alpha| {
+            materials.add(StandardMaterial {
+                base_color: Color::rgba(0.4, 0.8, 1.0, alpha),
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..Default::default()
+            })
+        };
+        Self {
+            target: ghost(0.35),
+            path: ghost(0.12),
+        }
+    }
+}
+
+// Poses along a synchronized joint move, as run_programs moves the joints
+fn joint_path(from: [f32; JOINT_COUNT], to: [f32; JOINT_COUNT]) -> Vec<[f32; JOINT_COUNT]> {
+    let mut target = JointState::default();
+    for (index, angle) in to.iter().enumerate() {
+        target.set(index, *angle);
+    }
+    (1..=SNAPSHOTS + 1)
+        .map(|step| {
+            let fraction = step as f32 / (SNAPSHOTS + 1) as f32;
+            let mut pose = from;
+            for (angle, target) in pose.iter_mut().zip(target.angles) {
+                *angle += (target - *angle) * fraction;
+            }
+            pose
+        })
+        .collect()
+}
+
+// Runs the linear move of cartesian_motion on a copy of the chain and picks poses along it
+fn linear_path(
+    base: &GlobalTransform,
+    frames: &[JointFrame; JOINT_COUNT],
+    tcp_offset: Transform,
+    from: [f32; JOINT_COUNT],
+    target: Vec3,
+) -> Vec<[f32; JOINT_COUNT]> {
+    let mut state = JointState { angles: from };
+    let mut poses = vec![from];
+    for _ in 0..MAX_LINEAR_STEPS {
+        let transforms = link_transforms(base, frames, &state.angles);
+        let (origins, axes) = chain_axes(base, &transforms, frames);
+        let tcp = flange_transform(&transforms[JOINT_COUNT - 1]).mul_transform(tcp_offset);
+        let jacobian = geometric_jacobian(&origins, &axes, tcp.translation);
+        match linear_step(&jacobian, tcp.translation, target, LINEAR_STEP) {
+            Some(step) => state.add(step),
+            None => break,
+        }
+        poses.push(state.angles);
+    }
+    (1..=SNAPSHOTS + 1)
+        .map(|step| poses[(poses.len() - 1) * step / (SNAPSHOTS + 1)])
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn show_ghosts(
+    mut commands: Commands,
+    preview: Res<GhostPreview>,
+    frames: Res<Frames>,
+    materials: Res<GhostMaterials>,
+    robots: Query<
+        (
+            &GlobalTransform,
+            &Handle<Mesh>,
+            &JointState,
+            &ServoControl,
+            &Manipulability,
+        ),
+        With<Robot>,
+    >,
+    joints: Query<(&Joint, &Transform, &Handle<Mesh>)>,
+    ghosts: Query<Entity, With<Ghost>>,
+    mut shown: Local<Option<GhostRequest>>,
+) {
+    let request = match (preview.robot, preview.target) {
+        (Some(robot), Some(target)) => robots
+            .get(robot)
+            .ok()
+            .map(|(base, _, state, servo, _)| (robot, target, servo.angles(state), *base)),
+        _ => None,
+    };
+    // Only replanned when the request or the robot changed
+    if request == *shown {
+        return;
+    }
+    for ghost in ghosts.iter() {
+        commands.entity(ghost).despawn();
+    }
+    *shown = request;
+    let (robot, target, angles, base) = match request {
+        Some(request) => request,
+        None => return,
+    };
+    let (_, base_mesh, _, _, manipulability) = match robots.get(robot) {
+        Ok(robot) => robot,
+        Err(_) => return,
+    };
+
+    let chain = joint_frames(
+        robot,
+        joints
+            .iter()
+            .map(|(joint, transform, _)| (joint, transform)),
+    );
+    let poses = match target {
+        GhostTarget::Joints { from, to } => joint_path(
+            from.map_or(angles, |from| from.map(f32::to_radians)),
+            to.map(f32::to_radians),
+        ),
+        GhostTarget::Linear(position) => {
+            // Keeps the tool or tool frame of the real robot on the ghost's TCP
+            let transforms = link_transforms(&base, &chain, &angles);
+            let flange = Transform::from(flange_transform(&transforms[JOINT_COUNT - 1]));
+            let tcp_offset = Transform::from_matrix(
+                flange.compute_matrix().inverse() * manipulability.tcp.compute_matrix(),
+            );
+            let target = frames.user_transform().mul_vec3(position);
+            linear_path(&base, &chain, tcp_offset, angles, target)
+        }
+    };
+
+    let mut link_meshes: Vec<Handle<Mesh>> = vec![Handle::default(); JOINT_COUNT];
+    for (joint, _, mesh) in joints.iter().filter(|(joint, ..)| joint.robot == robot) {
+        link_meshes[joint.index] = mesh.clone();
+    }
+    for (number, pose) in poses.iter().enumerate() {
+        let material = if number == poses.len() - 1 {
+            materials.target.clone()
+        } else {
+            materials.path.clone()
+        };
+        let transforms = link_transforms(&base, &chain, pose);
+        let parts = std::iter::once((base_mesh.clone(), base))
+            .chain(link_meshes.iter().cloned().zip(transforms));
+        for (mesh, transform) in parts {
+            commands
+                .spawn_bundle(PbrBundle {
+                    mesh,
+                    material: material.clone(),
+                    transform: transform.into(),
+                    ..Default::default()
+                })
+                .insert(Ghost)
+                .insert(NotShadowCaster);
+        }
+    }
+}
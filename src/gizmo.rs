@@ -0,0 +1,183 @@
+use bevy::{pbr::NotShadowCaster, prelude::*, render::view::NoFrustumCulling};
+
+use crate::{
+    kinematics::Manipulability,
+    robot::{Joint, JointState, Robot, JOINT_LIMITS},
+    servo::ServoControl,
+    trail::line_mesh,
+};
+
+// Line segments per full turn of a limit arc
+const ARC_SEGMENTS: usize = 64;
+
+pub struct GizmoSettings {
+    // RGB triads at the joint frames and the TCP
+    pub frames: bool,
+    // Arcs from the min to the max limit of each joint, with a line at the current angle
+    pub limits: bool,
+    // Length of the triad axes and radius of the arcs, m
+    pub size: f32,
+}
+
+impl Default for GizmoSettings {
+    fn default() -> Self {
+        Self {
+            frames: false,
+            limits: false,
+            size: 0.3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GizmoKind {
+    X,
+    Y,
+    Z,
+    Limits,
+    Angle,
+}
+
+impl GizmoKind {
+    const ALL: [GizmoKind; 5] = [
+        GizmoKind::X,
+        GizmoKind::Y,
+        GizmoKind::Z,
+        GizmoKind::Limits,
+        GizmoKind::Angle,
+    ];
+
+    fn color(&self) -> Color {
+        match self {
+            GizmoKind::X => Color::RED,
+            GizmoKind::Y => Color::GREEN,
+            GizmoKind::Z => Color::BLUE,
+            GizmoKind::Limits => Color::YELLOW,
+            GizmoKind::Angle => Color::WHITE,
+        }
+    }
+}
+
+// Lines of one color for all robots, rebuilt every frame while shown
+#[derive(Component, Debug)]
+pub struct GizmoLines {
+    kind: GizmoKind,
+}
+
+pub fn setup_gizmos(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for kind in GizmoKind::ALL {
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: meshes.add(line_mesh(vec![[0.0; 3]; 2])),
+                material: materials.add(StandardMaterial {
+                    base_color: kind.color(),
+                    unlit: true,
+                    ..Default::default()
+                }),
+                visibility: Visibility { is_visible: false },
+                ..Default::default()
+            })
+            .insert(GizmoLines { kind })
+            .insert(NoFrustumCulling)
+            .insert(NotShadowCaster);
+    }
+}
+
+fn push_triad(lines: &mut [Vec<[f32; 3]>], transform: &GlobalTransform, size: f32) {
+    let axes = [Vec3::X, Vec3::Y, Vec3::Z];
+    for (lines, axis) in lines.iter_mut().zip(axes) {
+        lines.push(transform.translation.to_array());
+        lines.push((transform.translation + transform.rotation * axis * size).to_array());
+    }
+}
+
+// Arc about the joint axis from the min to the max limit, zero is an arbitrary direction
+// normal to the axis that turns with the parent link
+fn push_limits(
+    limits: &mut Vec<[f32; 3]>,
+    angle_lines: &mut Vec<[f32; 3]>,
+    parent: Quat,
+    joint: &Joint,
+    origin: Vec3,
+    angle: f32,
+    radius: f32,
+) {
+    let reference = joint.axis.any_orthonormal_vector();
+    let point = |angle: f32| {
+        origin + parent * (Quat::from_axis_angle(joint.axis, angle) * reference) * radius
+    };
+    let (min, max) = JOINT_LIMITS[joint.index];
+    let (min, max) = (min.to_radians(), max.to_radians());
+    let segments = ((max - min) / std::f32::consts::TAU * ARC_SEGMENTS as f32).ceil() as usize;
+    for step in 0..segments.max(1) {
+        let a = min + (max - min) * step as f32 / segments as f32;
+        let b = min + (max - min) * (step + 1) as f32 / segments as f32;
+        limits.push(point(a).to_array());
+        limits.push(point(b).to_array());
+    }
+    for limit in [min, max] {
+        limits.push(origin.to_array());
+        limits.push(point(limit).to_array());
+    }
+    angle_lines.push(origin.to_array());
+    angle_lines.push(point(angle).to_array());
+}
+
+pub fn draw_gizmos(
+    settings: Res<GizmoSettings>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    robots: Query<(&Manipulability, &JointState, &ServoControl), With<Robot>>,
+    joints: Query<(&Joint, &GlobalTransform, &Parent)>,
+    parents: Query<&GlobalTransform>,
+    mut gizmos: Query<(&GizmoLines, &Handle<Mesh>, &mut Visibility)>,
+) {
+    let mut lines = vec![Vec::new(); GizmoKind::ALL.len()];
+    if settings.frames {
+        for (joint, transform, _) in joints.iter() {
+            if robots.contains(joint.robot) {
+                push_triad(&mut lines, transform, settings.size);
+            }
+        }
+        for (manipulability, ..) in robots.iter() {
+            push_triad(&mut lines, &manipulability.tcp.into(), settings.size);
+        }
+    }
+    if settings.limits {
+        let (limits, angles) = lines.split_at_mut(GizmoKind::Angle as usize);
+        for (joint, transform, parent) in joints.iter() {
+            let (state, servo) = match robots.get(joint.robot) {
+                Ok((_, state, servo)) => (state, servo),
+                Err(_) => continue,
+            };
+            let parent = parents
+                .get(parent.0)
+                .map_or(Quat::IDENTITY, |parent| parent.rotation);
+            push_limits(
+                &mut limits[GizmoKind::Limits as usize],
+                &mut angles[0],
+                parent,
+                joint,
+                transform.translation,
+                servo.angles(state)[joint.index],
+                settings.size * 0.8,
+            );
+        }
+    }
+
+    for (gizmo, handle, mut visibility) in gizmos.iter_mut() {
+        let positions = std::mem::take(&mut lines[gizmo.kind as usize]);
+        let visible = !positions.is_empty();
+        if visibility.is_visible != visible {
+            visibility.is_visible = visible;
+        }
+        if visible {
+            if let Some(mesh) = meshes.get_mut(handle) {
+                *mesh = line_mesh(positions);
+            }
+        }
+    }
+}
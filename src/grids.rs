@@ -0,0 +1,128 @@
+use bevy::prelude::*;
+
+use crate::{
+    frames::{Frame, Frames},
+    kinematics::Manipulability,
+    robot::ActiveRobot,
+    InfiniteGridBundle,
+};
+
+// Where a reference grid lies, the grid is drawn in the XZ plane of that frame
+#[derive(Debug, Clone, PartialEq)]
+pub enum GridPlacement {
+    Ground,
+    // User frame by name, so removing other frames does not move the grid
+    UserFrame(String),
+    // Horizontal plane through the TCP of the active robot, as a height reference
+    TcpHeight,
+}
+
+impl GridPlacement {
+    pub fn name(&self) -> String {
+        match self {
+            GridPlacement::Ground => "Ground".to_string(),
+            GridPlacement::UserFrame(frame) => format!("Frame {}", frame),
+            GridPlacement::TcpHeight => "TCP height".to_string(),
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone)]
+pub struct ReferenceGrid {
+    pub name: String,
+    pub placement: GridPlacement,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum GridCommand {
+    Add,
+    Remove(Entity),
+}
+
+pub fn spawn_grid(commands: &mut Commands, name: &str, placement: GridPlacement) -> Entity {
+    commands
+        .spawn_bundle(InfiniteGridBundle::default())
+        .insert(ReferenceGrid {
+            name: name.to_string(),
+            placement,
+        })
+        .id()
+}
+
+pub fn manage_grids(
+    mut commands: Commands,
+    mut events: EventReader<GridCommand>,
+    frames: Res<Frames>,
+    grids: Query<&ReferenceGrid>,
+) {
+    for command in events.iter() {
+        match *command {
+            GridCommand::Add => {
+                let mut number = grids.iter().count() + 1;
+                while grids
+                    .iter()
+                    .any(|grid| grid.name == format!("Grid {}", number))
+                {
+                    number += 1;
+                }
+                // New grids start on the active user frame, else at the TCP height
+                let placement = match frames.active_user {
+                    0 => None,
+                    index => frames.user.get(index),
+                }
+                .map_or(GridPlacement::TcpHeight, |frame| {
+                    GridPlacement::UserFrame(frame.name.clone())
+                });
+                spawn_grid(&mut commands, &format!("Grid {}", number), placement);
+            }
+            GridCommand::Remove(grid) => {
+                commands.entity(grid).despawn();
+            }
+        }
+    }
+}
+
+pub fn place_grids(
+    frames: Res<Frames>,
+    active: Res<ActiveRobot>,
+    robots: Query<&Manipulability>,
+    mut grids: Query<(&ReferenceGrid, &mut Transform)>,
+) {
+    for (grid, mut transform) in grids.iter_mut() {
+        let target =
+            match &grid.placement {
+                GridPlacement::Ground => Some(Transform::identity()),
+                GridPlacement::UserFrame(name) => frames
+                    .user
+                    .iter()
+                    .find(|frame| &frame.name == name)
+                    .map(Frame::transform),
+                GridPlacement::TcpHeight => active
+                    .entity
+                    .and_then(|robot| robots.get(robot).ok())
+                    .map(|manipulability| {
+                        Transform::from_xyz(0.0, manipulability.tcp.translation.y, 0.0)
+                    }),
+            };
+        // A grid whose frame was removed or renamed stays where it was
+        if let Some(target) = target {
+            if *transform != target {
+                *transform = target;
+            }
+        }
+    }
+}
+
+// Placements offered for a grid: the ground, the TCP height and every user frame but world
+pub fn grid_placements(frames: &Frames) -> Vec<GridPlacement> {
+    [GridPlacement::Ground, GridPlacement::TcpHeight]
+        .into_iter()
+        .chain(
+            frames
+                .user
+                .iter()
+                .skip(1)
+                .map(|frame| GridPlacement::UserFrame(frame.name.clone())),
+        )
+        .collect()
+}
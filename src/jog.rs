@@ -0,0 +1,233 @@
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+
+use crate::{
+    cartesian::{CartesianJog, JogFrame},
+    kinematics::JOINT_COUNT,
+    robot::{ActiveRobot, JointState, JOINT_NAMES},
+};
+
+// Joint speed at 100 %, deg/s
+const JOINT_JOG_SPEED: f32 = 60.0;
+// Speed keys and gamepad shoulder buttons change the override by this many percent
+const SPEED_STEP: f32 = 10.0;
+const STICK_DEADZONE: f32 = 0.15;
+const CARTESIAN_AXES: [&str; 6] = ["X", "Y", "Z", "Rx", "Ry", "Rz"];
+const AXIS_KEYS: [KeyCode; 6] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JogMode {
+    Joint,
+    Cartesian,
+}
+
+impl JogMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            JogMode::Joint => "Joint",
+            JogMode::Cartesian => "Cartesian",
+        }
+    }
+
+    pub fn axis_count(&self) -> usize {
+        match self {
+            JogMode::Joint => JOINT_COUNT,
+            JogMode::Cartesian => CARTESIAN_AXES.len(),
+        }
+    }
+
+    pub fn axis_name(&self, axis: usize) -> &'static str {
+        match self {
+            JogMode::Joint => JOINT_NAMES[axis],
+            JogMode::Cartesian => CARTESIAN_AXES[axis],
+        }
+    }
+}
+
+// Percent of the jog, pose recall and programmed speeds of every robot, set from the panel, the
+// pendant and the speed keys
+pub struct SpeedOverride {
+    pub percent: f32,
+}
+
+impl Default for SpeedOverride {
+    fn default() -> Self {
+        Self { percent: 100.0 }
+    }
+}
+
+impl SpeedOverride {
+    pub fn scale(&self) -> f32 {
+        self.percent / 100.0
+    }
+
+    fn step(&mut self, step: f32) {
+        self.percent = (self.percent + step).clamp(1.0, 100.0);
+    }
+}
+
+// Axis the keyboard and gamepad jog, the Cartesian frame is the one of `CartesianJog`
+pub struct JogSettings {
+    pub mode: JogMode,
+    pub axis: usize,
+    // Direction and strength of the current jog, -1 to 1, for the UI
+    pub input: f32,
+}
+
+impl Default for JogSettings {
+    fn default() -> Self {
+        Self {
+            mode: JogMode::Joint,
+            axis: 0,
+            input: 0.0,
+        }
+    }
+}
+
+impl JogSettings {
+    pub fn set_mode(&mut self, mode: JogMode) {
+        self.mode = mode;
+        self.axis = self.axis.min(mode.axis_count() - 1);
+    }
+
+    fn step_axis(&mut self, step: isize) {
+        let count = self.mode.axis_count() as isize;
+        self.axis = (self.axis as isize + step).rem_euclid(count) as usize;
+    }
+}
+
+fn next_frame(frame: JogFrame) -> JogFrame {
+    let index = JogFrame::ALL.iter().position(|other| *other == frame);
+    JogFrame::ALL[index.map_or(0, |index| (index + 1) % JogFrame::ALL.len())]
+}
+
+// Keys: 1-6 select the axis, left and right arrows jog it, Tab switches joint and Cartesian
+// mode, F the Cartesian frame, page up and down the speed. On a gamepad the left stick jogs,
+// the D-pad selects the axis, North switches mode, West the frame and the shoulders the speed.
+#[allow(clippy::too_many_arguments)]
+pub fn jog_input(
+    time: Res<Time>,
+    mut egui_ctx: ResMut<EguiContext>,
+    keys: Res<Input<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    mut settings: ResMut<JogSettings>,
+    mut speed_override: ResMut<SpeedOverride>,
+    mut jog: ResMut<CartesianJog>,
+    active: Res<ActiveRobot>,
+    mut robots: Query<&mut JointState>,
+) {
+    let mut input = 0.0;
+    if !egui_ctx.ctx_mut().wants_keyboard_input() {
+        let mode = settings.mode;
+        if let Some(axis) = AXIS_KEYS[..mode.axis_count()]
+            .iter()
+            .position(|key| keys.just_pressed(*key))
+        {
+            settings.axis = axis;
+        }
+        if keys.just_pressed(KeyCode::Tab) {
+            settings.set_mode(match mode {
+                JogMode::Joint => JogMode::Cartesian,
+                JogMode::Cartesian => JogMode::Joint,
+            });
+        }
+        if keys.just_pressed(KeyCode::F) {
+            jog.frame = next_frame(jog.frame);
+        }
+        if keys.just_pressed(KeyCode::PageUp) {
+            speed_override.step(SPEED_STEP);
+        }
+        if keys.just_pressed(KeyCode::PageDown) {
+            speed_override.step(-SPEED_STEP);
+        }
+        if keys.pressed(KeyCode::Right) {
+            input += 1.0;
+        }
+        if keys.pressed(KeyCode::Left) {
+            input -= 1.0;
+        }
+    }
+
+    for gamepad in gamepads.iter().copied() {
+        let pressed = |button| buttons.just_pressed(GamepadButton(gamepad, button));
+        if pressed(GamepadButtonType::North) {
+            let mode = match settings.mode {
+                JogMode::Joint => JogMode::Cartesian,
+                JogMode::Cartesian => JogMode::Joint,
+            };
+            settings.set_mode(mode);
+        }
+        if pressed(GamepadButtonType::West) {
+            jog.frame = next_frame(jog.frame);
+        }
+        if pressed(GamepadButtonType::DPadRight) {
+            settings.step_axis(1);
+        }
+        if pressed(GamepadButtonType::DPadLeft) {
+            settings.step_axis(-1);
+        }
+        if pressed(GamepadButtonType::RightTrigger) {
+            speed_override.step(SPEED_STEP);
+        }
+        if pressed(GamepadButtonType::LeftTrigger) {
+            speed_override.step(-SPEED_STEP);
+        }
+        let stick = axes
+            .get(GamepadAxis(gamepad, GamepadAxisType::LeftStickX))
+            .unwrap_or(0.0);
+        if stick.abs() > STICK_DEADZONE {
+            input += stick;
+        }
+    }
+    let input = input.clamp(-1.0, 1.0);
+    if settings.input != input {
+        settings.input = input;
+    }
+    if input == 0.0 {
+        return;
+    }
+
+    let mut joint_state = match active.entity.map(|robot| robots.get_mut(robot)) {
+        Some(Ok(joint_state)) => joint_state,
+        _ => return,
+    };
+    jog_axis(
+        settings.mode,
+        settings.axis,
+        input * speed_override.scale(),
+        time.delta_seconds(),
+        &mut jog,
+        &mut joint_state,
+    );
+}
+
+// Moves one axis at a fraction of the full jog speed, -1 to 1, also used by the teach pendant
+pub fn jog_axis(
+    mode: JogMode,
+    axis: usize,
+    scale: f32,
+    dt: f32,
+    jog: &mut CartesianJog,
+    joint_state: &mut JointState,
+) {
+    match mode {
+        JogMode::Joint => {
+            let angle = joint_state.angles[axis] + (scale * JOINT_JOG_SPEED).to_radians() * dt;
+            joint_state.set(axis, angle);
+        }
+        // Picked up by the Cartesian motion like the jog buttons of the side panel
+        JogMode::Cartesian => match axis {
+            0..=2 => jog.linear[axis] = scale,
+            _ => jog.angular[axis - 3] = scale,
+        },
+    }
+}
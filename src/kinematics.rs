@@ -0,0 +1,460 @@
+use bevy::prelude::*;
+
+use crate::{
+    frames::Frames,
+    robot::{Joint, Robot, FLANGE_OFFSET},
+    tool::Tool,
+};
+
+pub const JOINT_COUNT: usize = 5;
+
+// Rows are [vx, vy, vz, wx, wy, wz], columns are joints from base to wrist
+pub type Jacobian = [[f32; JOINT_COUNT]; 6];
+
+// Below this ratio of smallest to largest singular value the arm is reported as near singular
+pub const SINGULARITY_THRESHOLD: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Singularity {
+    Shoulder,
+    Elbow,
+    Wrist,
+}
+
+impl Singularity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Singularity::Shoulder => "shoulder",
+            Singularity::Elbow => "elbow",
+            Singularity::Wrist => "wrist",
+        }
+    }
+}
+
+// Jacobian of a robot's chain and the measures derived from it, refreshed every frame
+#[derive(Component, Debug, Clone)]
+pub struct Manipulability {
+    // World joint origins and axes the Jacobian was built from
+    pub origins: [Vec3; JOINT_COUNT],
+    pub axes: [Vec3; JOINT_COUNT],
+    pub jacobian: Jacobian,
+    pub tcp: Transform,
+    pub singular_values: [f32; JOINT_COUNT],
+    pub yoshikawa: f32,
+    pub condition: f32,
+    pub singularity: Option<Singularity>,
+}
+
+impl Default for Manipulability {
+    fn default() -> Self {
+        Self {
+            origins: [Vec3::ZERO; JOINT_COUNT],
+            axes: [Vec3::ZERO; JOINT_COUNT],
+            jacobian: [[0.0; JOINT_COUNT]; 6],
+            tcp: Transform::identity(),
+            singular_values: [0.0; JOINT_COUNT],
+            yoshikawa: 0.0,
+            condition: f32::INFINITY,
+            singularity: None,
+        }
+    }
+}
+
+impl Manipulability {
+    pub fn from_jacobian(jacobian: Jacobian, tcp: Transform) -> Self {
+        let (eigenvalues, eigenvectors) = symmetric_eigen(transpose_product(&jacobian));
+        let mut singular_values = [0.0; JOINT_COUNT];
+        for i in 0..JOINT_COUNT {
+            singular_values[i] = eigenvalues[i].max(0.0).sqrt();
+        }
+        let (min_index, min) = singular_values
+            .iter()
+            .copied()
+            .enumerate()
+            .fold((0, f32::INFINITY), |a, b| if b.1 < a.1 { b } else { a });
+        let max = singular_values.iter().copied().fold(0.0, f32::max);
+        let condition = if min > f32::EPSILON {
+            max / min
+        } else {
+            f32::INFINITY
+        };
+
+        let singularity = if max > 0.0 && min / max < SINGULARITY_THRESHOLD {
+            // The joint that dominates the null-space direction tells which part of the arm is stuck
+            let dominant = (0..JOINT_COUNT)
+                .max_by(|a, b| {
+                    eigenvectors[*a][min_index]
+                        .abs()
+                        .total_cmp(&eigenvectors[*b][min_index].abs())
+                })
+                .unwrap();
+            Some(match dominant {
+                0 => Singularity::Shoulder,
+                1 | 2 => Singularity::Elbow,
+                _ => Singularity::Wrist,
+            })
+        } else {
+            None
+        };
+
+        Self {
+            origins: [Vec3::ZERO; JOINT_COUNT],
+            axes: [Vec3::ZERO; JOINT_COUNT],
+            jacobian,
+            tcp,
+            singular_values,
+            yoshikawa: singular_values.iter().product(),
+            condition,
+            singularity,
+        }
+    }
+}
+
+// Fixed geometry of a joint: position and rest orientation in the parent link frame, rotation axis
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JointFrame {
+    pub offset: Vec3,
+    pub axis: Vec3,
+    pub home: Quat,
+}
+
+// Joint frames of one robot from the local transforms of its links
+pub fn joint_frames<'a>(
+    robot: Entity,
+    joints: impl Iterator<Item = (&'a Joint, &'a Transform)>,
+) -> [JointFrame; JOINT_COUNT] {
+    let mut frames = [JointFrame::default(); JOINT_COUNT];
+    for (joint, transform) in joints.filter(|(joint, _)| joint.robot == robot) {
+        frames[joint.index] = JointFrame {
+            offset: transform.translation,
+            axis: joint.axis,
+            home: joint.home,
+        };
+    }
+    frames
+}
+
+// Link transforms at the given angles, computed directly instead of through the scene hierarchy
+pub fn link_transforms(
+    base: &GlobalTransform,
+    frames: &[JointFrame; JOINT_COUNT],
+    angles: &[f32; JOINT_COUNT],
+) -> [GlobalTransform; JOINT_COUNT] {
+    let mut transforms = [GlobalTransform::identity(); JOINT_COUNT];
+    let mut parent = *base;
+    for ((transform, frame), angle) in transforms.iter_mut().zip(frames).zip(angles) {
+        parent = parent.mul_transform(Transform {
+            translation: frame.offset,
+            rotation: Quat::from_axis_angle(frame.axis, *angle) * frame.home,
+            ..Default::default()
+        });
+        *transform = parent;
+    }
+    transforms
+}
+
+// World joint origins and axes of a chain of link transforms
+pub fn chain_axes(
+    base: &GlobalTransform,
+    transforms: &[GlobalTransform; JOINT_COUNT],
+    frames: &[JointFrame; JOINT_COUNT],
+) -> ([Vec3; JOINT_COUNT], [Vec3; JOINT_COUNT]) {
+    let mut origins = [Vec3::ZERO; JOINT_COUNT];
+    let mut axes = [Vec3::ZERO; JOINT_COUNT];
+    let mut parent_rotation = base.rotation;
+    for (index, (transform, frame)) in transforms.iter().zip(frames).enumerate() {
+        origins[index] = transform.translation;
+        axes[index] = parent_rotation * frame.axis;
+        parent_rotation = transform.rotation;
+    }
+    (origins, axes)
+}
+
+pub fn geometric_jacobian(
+    origins: &[Vec3; JOINT_COUNT],
+    axes: &[Vec3; JOINT_COUNT],
+    tcp: Vec3,
+) -> Jacobian {
+    let mut jacobian = [[0.0; JOINT_COUNT]; 6];
+    for i in 0..JOINT_COUNT {
+        let linear = axes[i].cross(tcp - origins[i]);
+        for row in 0..3 {
+            jacobian[row][i] = linear[row];
+            jacobian[row + 3][i] = axes[i][row];
+        }
+    }
+    jacobian
+}
+
+// Damped least squares: dq = (J^T W J + λ²I)^-1 J^T W v, stays bounded near singularities.
+// Weights pick which twist components matter, e.g. zero the angular rows for position-only moves.
+pub fn ik_step(
+    jacobian: &Jacobian,
+    twist: [f32; 6],
+    weights: [f32; 6],
+    damping: f32,
+) -> [f32; JOINT_COUNT] {
+    let mut weighted = *jacobian;
+    let mut target = twist;
+    for row in 0..6 {
+        for value in weighted[row].iter_mut() {
+            *value *= weights[row];
+        }
+        target[row] *= weights[row];
+    }
+    let mut lhs = transpose_product(&weighted);
+    for (i, row) in lhs.iter_mut().enumerate() {
+        row[i] += damping * damping;
+    }
+    let mut rhs = [0.0; JOINT_COUNT];
+    for (i, value) in rhs.iter_mut().enumerate() {
+        *value = (0..6).map(|row| weighted[row][i] * target[row]).sum();
+    }
+    solve(lhs, rhs)
+}
+
+fn transpose_product(jacobian: &Jacobian) -> [[f32; JOINT_COUNT]; JOINT_COUNT] {
+    let mut product = [[0.0; JOINT_COUNT]; JOINT_COUNT];
+    for i in 0..JOINT_COUNT {
+        for j in 0..JOINT_COUNT {
+            product[i][j] = (0..6).map(|row| jacobian[row][i] * jacobian[row][j]).sum();
+        }
+    }
+    product
+}
+
+// Gaussian elimination with partial pivoting
+pub fn solve<const N: usize>(mut a: [[f32; N]; N], mut b: [f32; N]) -> [f32; N] {
+    for col in 0..N {
+        let pivot = (col..N)
+            .max_by(|x, y| a[*x][col].abs().total_cmp(&a[*y][col].abs()))
+            .unwrap();
+        if a[pivot][col].abs() < f32::EPSILON {
+            continue;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let pivot_row = a[col];
+        for row in col + 1..N {
+            let factor = a[row][col] / pivot_row[col];
+            for (k, value) in a[row].iter_mut().enumerate().skip(col) {
+                *value -= factor * pivot_row[k];
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = [0.0; N];
+    for row in (0..N).rev() {
+        if a[row][row].abs() < f32::EPSILON {
+            continue;
+        }
+        let sum: f32 = (row + 1..N).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    x
+}
+
+// Cyclic Jacobi rotations, returns eigenvalues and eigenvectors stored as columns
+fn symmetric_eigen<const N: usize>(mut a: [[f32; N]; N]) -> ([f32; N], [[f32; N]; N]) {
+    let mut v = [[0.0; N]; N];
+    for (i, row) in v.iter_mut().enumerate() {
+        row[i] = 1.0;
+    }
+    for _ in 0..50 {
+        let off: f32 = (0..N)
+            .flat_map(|i| (0..N).filter(move |j| *j != i).map(move |j| (i, j)))
+            .map(|(i, j)| a[i][j] * a[i][j])
+            .sum();
+        if off < 1e-12 {
+            break;
+        }
+        for p in 0..N {
+            for q in p + 1..N {
+                if a[p][q].abs() < 1e-12 {
+                    continue;
+                }
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for row in a.iter_mut().chain(v.iter_mut()) {
+                    let (rp, rq) = (row[p], row[q]);
+                    row[p] = c * rp - s * rq;
+                    row[q] = s * rp + c * rq;
+                }
+                let (row_p, row_q) = (a[p], a[q]);
+                for k in 0..N {
+                    a[p][k] = c * row_p[k] - s * row_q[k];
+                    a[q][k] = s * row_p[k] + c * row_q[k];
+                }
+            }
+        }
+    }
+    let mut eigenvalues = [0.0; N];
+    for (i, value) in eigenvalues.iter_mut().enumerate() {
+        *value = a[i][i];
+    }
+    (eigenvalues, v)
+}
+
+pub fn update_jacobian(
+    joints: Query<(&Joint, &GlobalTransform, &Parent)>,
+    parents: Query<&GlobalTransform>,
+    tools: Query<(&Tool, &GlobalTransform)>,
+    frames: Res<Frames>,
+    mut robots: Query<(Entity, &mut Manipulability), With<Robot>>,
+) {
+    for (robot, mut manipulability) in robots.iter_mut() {
+        let mut origins = [Vec3::ZERO; JOINT_COUNT];
+        let mut axes = [Vec3::ZERO; JOINT_COUNT];
+        let mut flange = GlobalTransform::identity();
+        for (joint, transform, parent) in joints.iter().filter(|(joint, ..)| joint.robot == robot) {
+            // Joints rotate about an axis fixed in the parent frame
+            let parent_rotation = parents
+                .get(parent.0)
+                .map_or(Quat::IDENTITY, |parent| parent.rotation);
+            origins[joint.index] = transform.translation;
+            axes[joint.index] = parent_rotation * joint.axis;
+            if joint.index == JOINT_COUNT - 1 {
+                flange = transform.mul_transform(Transform::from_translation(FLANGE_OFFSET));
+            }
+        }
+        // An active tool frame overrides the TCP of the mounted tool
+        let tool = tools.iter().find(|(tool, _)| tool.robot == robot);
+        let tcp = match (frames.tool_offset(), tool) {
+            (Some(offset), _) => flange.mul_transform(offset),
+            (None, Some((tool, transform))) => {
+                transform.mul_transform(Transform::from_translation(tool.tcp))
+            }
+            (None, None) => flange,
+        };
+        let tcp = Transform::from(tcp);
+        *manipulability = Manipulability {
+            origins,
+            axes,
+            ..Manipulability::from_jacobian(
+                geometric_jacobian(&origins, &axes, tcp.translation),
+                tcp,
+            )
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANGLES: [f32; JOINT_COUNT] = [0.3, -0.6, 0.9, 0.4, -0.2];
+
+    // Joint origins, world axes and TCP of a test chain, built link by link
+    fn forward(
+        angles: &[f32; JOINT_COUNT],
+    ) -> ([Vec3; JOINT_COUNT], [Vec3; JOINT_COUNT], Transform) {
+        // Offset from the previous joint and rotation axis, both in the parent link frame
+        let chain = [
+            (Vec3::new(1.0, 0.3, -0.5), Vec3::Y),
+            (Vec3::new(0.1, 0.2, 0.0), Vec3::Z),
+            (Vec3::new(0.0, 0.6, 0.0), Vec3::Z),
+            (Vec3::new(0.0, 0.5, 0.1), Vec3::X),
+            (Vec3::new(0.0, 0.1, 0.0), Vec3::Y),
+        ];
+        let mut link = Transform::from_rotation(Quat::from_rotation_y(0.7));
+        let mut origins = [Vec3::ZERO; JOINT_COUNT];
+        let mut axes = [Vec3::ZERO; JOINT_COUNT];
+        for (index, (offset, axis)) in chain.into_iter().enumerate() {
+            origins[index] = link.mul_vec3(offset);
+            axes[index] = link.rotation * axis;
+            link = link.mul_transform(Transform {
+                translation: offset,
+                rotation: Quat::from_axis_angle(axis, angles[index]),
+                ..Default::default()
+            });
+        }
+        // Off the last joint axis, so the wrist joint moves it too
+        (
+            origins,
+            axes,
+            link.mul_transform(Transform::from_xyz(0.1, 0.15, 0.05)),
+        )
+    }
+
+    #[test]
+    fn jacobian_matches_finite_differences() {
+        let (origins, axes, tcp) = forward(&ANGLES);
+        let jacobian = geometric_jacobian(&origins, &axes, tcp.translation);
+
+        let step = 1e-3;
+        for joint in 0..JOINT_COUNT {
+            let mut plus = ANGLES;
+            let mut minus = ANGLES;
+            plus[joint] += step;
+            minus[joint] -= step;
+            let (plus, minus) = (forward(&plus).2, forward(&minus).2);
+            let linear = (plus.translation - minus.translation) / (2.0 * step);
+            let (axis, angle) = (plus.rotation * minus.rotation.inverse()).to_axis_angle();
+            let angular = axis * angle / (2.0 * step);
+            for row in 0..3 {
+                assert!(
+                    (jacobian[row][joint] - linear[row]).abs() < 1e-2,
+                    "linear row {} joint {}: {} != {}",
+                    row,
+                    joint,
+                    jacobian[row][joint],
+                    linear[row]
+                );
+                assert!(
+                    (jacobian[row + 3][joint] - angular[row]).abs() < 1e-2,
+                    "angular row {} joint {}: {} != {}",
+                    row,
+                    joint,
+                    jacobian[row + 3][joint],
+                    angular[row]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn symmetric_eigen_reconstructs_input() {
+        let a = [
+            [4.0, 1.0, -2.0, 0.5],
+            [1.0, 3.0, 0.0, 1.5],
+            [-2.0, 0.0, 5.0, -1.0],
+            [0.5, 1.5, -1.0, 2.0],
+        ];
+        let (values, vectors) = symmetric_eigen(a);
+        for i in 0..4 {
+            for j in 0..4 {
+                // A = V diag(values) V^T
+                let sum: f32 = (0..4)
+                    .map(|k| vectors[i][k] * values[k] * vectors[j][k])
+                    .sum();
+                assert!(
+                    (sum - a[i][j]).abs() < 1e-4,
+                    "[{}][{}]: {} != {}",
+                    i,
+                    j,
+                    sum,
+                    a[i][j]
+                );
+                // The eigenvectors are orthonormal
+                let dot: f32 = (0..4).map(|k| vectors[k][i] * vectors[k][j]).sum();
+                let identity = if i == j { 1.0 } else { 0.0 };
+                assert!((dot - identity).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn solve_recovers_solution() {
+        let a = [[2.0, 1.0, 0.0], [1.0, 3.0, 1.0], [0.0, 1.0, 4.0]];
+        let x = [1.0, -2.0, 0.5];
+        let mut b = [0.0; 3];
+        for (row, value) in b.iter_mut().enumerate() {
+            *value = (0..3).map(|col| a[row][col] * x[col]).sum();
+        }
+        for (solved, expected) in solve(a, b).iter().zip(x) {
+            assert!((solved - expected).abs() < 1e-5);
+        }
+    }
+}
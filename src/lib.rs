@@ -1,10 +1,27 @@
-pub mod elbow;
-pub mod lower_arm;
+pub mod camera;
+pub mod cartesian;
+pub mod collision;
+pub mod conveyor;
+pub mod dynamics;
+pub mod frames;
+pub mod ghost;
+pub mod gizmo;
+pub mod grids;
+pub mod jog;
+pub mod kinematics;
+pub mod manipulator;
+pub mod pendant;
+pub mod picking;
+pub mod poses;
+pub mod program;
+pub mod report;
 pub mod robot;
-pub mod shoulder;
+pub mod scene;
+pub mod servo;
 pub mod side_panel;
-pub mod upper_arm;
-pub mod wrist;
+pub mod tool;
+pub mod trail;
+pub mod viewport;
 
 use std::borrow::Cow;
 
@@ -19,6 +36,7 @@ use bevy::{
     reflect::TypeUuid,
     render::{
         mesh::PrimitiveTopology,
+        primitives::Aabb,
         render_phase::{
             AddRenderCommand, DrawFunctions, EntityRenderCommand, RenderCommandResult, RenderPhase,
             SetItemPipeline,
@@ -41,6 +59,9 @@ use bevy::{
 
 static SHADER: &str = include_str!("shader.wgsl");
 
+// Mesh bounding boxes that cast shadows onto the grids, the rest is ignored
+const MAX_SHADOW_BOXES: usize = 64;
+
 const SHADER_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 15204473893972682982);
 
@@ -59,7 +80,9 @@ impl Plugin for InfiniteGridPlugin {
             .init_resource::<InfiniteGridPipeline>()
             .init_resource::<SpecializedRenderPipelines<InfiniteGridPipeline>>()
             .add_render_command::<Transparent3d, DrawInfiniteGrid>()
+            .init_resource::<ExtractedGridShadows>()
             .add_system_to_stage(RenderStage::Extract, extract_infinite_grids)
+            .add_system_to_stage(RenderStage::Extract, extract_grid_shadows)
             .add_system_to_stage(RenderStage::Prepare, prepare_infinite_grids)
             .add_system_to_stage(RenderStage::Prepare, prepare_grid_view_bind_groups)
             .add_system_to_stage(RenderStage::Queue, queue_infinite_grids)
@@ -67,11 +90,55 @@ impl Plugin for InfiniteGridPlugin {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GridUnit {
+    Millimeter,
+    Centimeter,
+    Meter,
+}
+
+impl GridUnit {
+    pub const ALL: [GridUnit; 3] = [GridUnit::Millimeter, GridUnit::Centimeter, GridUnit::Meter];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GridUnit::Millimeter => "mm",
+            GridUnit::Centimeter => "cm",
+            GridUnit::Meter => "m",
+        }
+    }
+
+    // Meters per unit
+    pub fn factor(&self) -> f32 {
+        match self {
+            GridUnit::Millimeter => 0.001,
+            GridUnit::Centimeter => 0.01,
+            GridUnit::Meter => 1.0,
+        }
+    }
+}
+
 #[derive(Component, Copy, Clone)]
 pub struct InfiniteGrid {
     pub x_axis_color: Color,
     pub z_axis_color: Color,
     pub shadow_color: Color,
+    pub minor_line_color: Color,
+    pub major_line_color: Color,
+    // Size of a minor cell in meters, replaces the transform scale as the line spacing
+    pub cell_size: f32,
+    // Unit the cell size is shown and edited in
+    pub unit: GridUnit,
+    // Minor cells per major cell, 1 draws only major lines
+    pub subdivisions: u32,
+    // Line width in pixels
+    pub line_width: f32,
+    // Distance from the camera in meters at which the lines have faded out
+    pub fade_distance: f32,
+    // Darkens the grid below meshes, projected along the grid normal
+    pub shadows: bool,
+    // Distance in meters over which the contact shadow around touching objects fades
+    pub shadow_softness: f32,
 }
 
 #[derive(Component)]
@@ -86,6 +153,15 @@ impl Default for InfiniteGrid {
             x_axis_color: Color::rgb(1.0, 0.2, 0.2),
             z_axis_color: Color::rgb(0.2, 0.2, 1.0),
             shadow_color: Color::rgba(0.2, 0.2, 0.2, 0.7),
+            minor_line_color: Color::rgb(0.2, 0.2, 0.2),
+            major_line_color: Color::rgb(0.35, 0.35, 0.35),
+            cell_size: 0.1,
+            unit: GridUnit::Meter,
+            subdivisions: 10,
+            line_width: 1.0,
+            fade_distance: 100.0,
+            shadows: true,
+            shadow_softness: 0.3,
         }
     }
 }
@@ -100,6 +176,21 @@ pub struct InfiniteGridUniform {
     x_axis_color: Vec3,
     z_axis_color: Vec3,
     shadow_color: Vec4,
+    minor_line_color: Vec4,
+    major_line_color: Vec4,
+    subdivisions: f32,
+    line_width: f32,
+    fade_distance: f32,
+    shadow_softness: f32,
+    box_count: u32,
+    // Per box the three edge vectors from the center to the faces, then the center
+    boxes: [Vec4; MAX_SHADOW_BOXES * 4],
+}
+
+// Oriented bounding boxes of the shadow casting meshes in world space
+#[derive(Default)]
+struct ExtractedGridShadows {
+    boxes: Vec<[Vec4; 4]>,
 }
 
 #[derive(Default)]
@@ -243,8 +334,58 @@ fn extract_infinite_grids(
     }
 }
 
+// Visible meshes with bounds, entities marked NotShadowCaster (like the grid itself) are skipped.
+// When there are more than fit into the uniform, the ones nearest to a camera are kept.
+fn extract_grid_shadows(
+    mut commands: Commands,
+    casters: Query<(&Aabb, &GlobalTransform, &ComputedVisibility), Without<NotShadowCaster>>,
+    cameras: Query<&GlobalTransform, With<Camera>>,
+    mut warned: Local<bool>,
+) {
+    let eyes: Vec<Vec3> = cameras.iter().map(|camera| camera.translation).collect();
+    let mut boxes: Vec<(f32, [Vec4; 4])> = casters
+        .iter()
+        .filter(|(_, _, visibility)| visibility.is_visible)
+        .map(|(aabb, transform, _)| {
+            let matrix = transform.compute_matrix();
+            let half = Vec3::from(aabb.half_extents);
+            let center = matrix.transform_point3(aabb.center.into());
+            let distance = eyes
+                .iter()
+                .map(|eye| eye.distance_squared(center))
+                .fold(f32::INFINITY, f32::min);
+            let shadow_box = [
+                (matrix.x_axis * half.x).truncate().extend(0.0),
+                (matrix.y_axis * half.y).truncate().extend(0.0),
+                (matrix.z_axis * half.z).truncate().extend(0.0),
+                center.extend(1.0),
+            ];
+            (distance, shadow_box)
+        })
+        .collect();
+    if boxes.len() > MAX_SHADOW_BOXES {
+        if !*warned {
+            warn!(
+                "{} meshes cast grid shadows, only the {} nearest to a camera are drawn",
+                boxes.len(),
+                MAX_SHADOW_BOXES
+            );
+            *warned = true;
+        }
+        boxes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        boxes.truncate(MAX_SHADOW_BOXES);
+    }
+    commands.insert_resource(ExtractedGridShadows {
+        boxes: boxes
+            .into_iter()
+            .map(|(_, shadow_box)| shadow_box)
+            .collect(),
+    });
+}
+
 fn prepare_infinite_grids(
     mut commands: Commands,
+    shadows: Res<ExtractedGridShadows>,
     grids: Query<(Entity, &ExtractedInfiniteGrid)>,
     mut uniforms: ResMut<InfiniteGridUniforms>,
     render_device: Res<RenderDevice>,
@@ -253,6 +394,11 @@ fn prepare_infinite_grids(
     uniforms.uniforms.clear();
     for (entity, extracted) in grids.iter() {
         let transform = extracted.transform;
+        let grid = &extracted.grid;
+        let mut boxes = [Vec4::ZERO; MAX_SHADOW_BOXES * 4];
+        for (slot, shadow_box) in boxes.chunks_mut(4).zip(shadows.boxes.iter()) {
+            slot.copy_from_slice(shadow_box);
+        }
         let offset = transform.translation;
         let normal = transform.local_y();
         let rot_matrix = Mat3::from_quat(transform.rotation.inverse());
@@ -261,10 +407,22 @@ fn prepare_infinite_grids(
                 rot_matrix,
                 offset,
                 normal,
-                scale: transform.scale.x,
-                x_axis_color: Vec3::from_slice(&extracted.grid.x_axis_color.as_rgba_f32()),
-                z_axis_color: Vec3::from_slice(&extracted.grid.z_axis_color.as_rgba_f32()),
-                shadow_color: Vec4::from_slice(&extracted.grid.shadow_color.as_rgba_f32()),
+                scale: 1.0 / grid.cell_size.max(f32::EPSILON),
+                x_axis_color: Vec3::from_slice(&grid.x_axis_color.as_rgba_f32()),
+                z_axis_color: Vec3::from_slice(&grid.z_axis_color.as_rgba_f32()),
+                shadow_color: Vec4::from_slice(&grid.shadow_color.as_rgba_f32()),
+                minor_line_color: Vec4::from_slice(&grid.minor_line_color.as_rgba_f32()),
+                major_line_color: Vec4::from_slice(&grid.major_line_color.as_rgba_f32()),
+                subdivisions: grid.subdivisions.max(1) as f32,
+                line_width: grid.line_width,
+                fade_distance: grid.fade_distance.max(f32::EPSILON),
+                shadow_softness: grid.shadow_softness.max(f32::EPSILON),
+                box_count: if grid.shadows {
+                    shadows.boxes.len() as u32
+                } else {
+                    0
+                },
+                boxes,
             }),
         });
     }
@@ -1,5 +1,6 @@
 use bevy::{
     core::FixedTimestep,
+    pbr::NotShadowCaster,
     prelude::*,
     window::{PresentMode, WindowMode},
 };
@@ -7,14 +8,31 @@ use bevy_egui::EguiPlugin;
 use bevy_flycam::{FlyCam, MovementSettings, NoCameraPlayerPlugin};
 use bevy_obj::*;
 
-use robots_sim::elbow::*;
-use robots_sim::lower_arm::*;
+use robots_sim::camera::*;
+use robots_sim::cartesian::*;
+use robots_sim::collision::*;
+use robots_sim::conveyor::*;
+use robots_sim::dynamics::*;
+use robots_sim::frames::*;
+use robots_sim::ghost::*;
+use robots_sim::gizmo::*;
+use robots_sim::grids::*;
+use robots_sim::jog::*;
+use robots_sim::kinematics::*;
+use robots_sim::manipulator::*;
+use robots_sim::pendant::*;
+use robots_sim::picking::*;
+use robots_sim::poses::*;
+use robots_sim::program::*;
+use robots_sim::report::*;
 use robots_sim::robot::*;
-use robots_sim::shoulder::*;
+use robots_sim::scene::*;
+use robots_sim::servo::*;
 use robots_sim::side_panel::*;
-use robots_sim::upper_arm::*;
-use robots_sim::wrist::*;
-use robots_sim::{InfiniteGridBundle, InfiniteGridPlugin};
+use robots_sim::tool::*;
+use robots_sim::trail::*;
+use robots_sim::viewport::*;
+use robots_sim::InfiniteGridPlugin;
 
 const TIME_STEP: f32 = 1.0 / 60.0;
 fn main() {
@@ -28,17 +46,106 @@ fn main() {
         .add_plugins(DefaultPlugins)
         .add_plugin(EguiPlugin)
         .init_resource::<UiState>()
-        .add_system(ui_example)
+        .init_resource::<ActiveRobot>()
+        .add_event::<RobotCommand>()
+        .add_system(manage_robots)
+        .add_system(select_active_robot.before(ui_example))
+        .init_resource::<RobotCollisions>()
+        .add_system(check_robot_collisions.after(update_jacobian))
+        .init_resource::<CartesianJog>()
+        .init_resource::<CartesianMove>()
+        .insert_resource(Frames::load(FRAMES_FILE).unwrap_or_default())
+        .add_system(update_jacobian)
+        .add_system(ui_example.after(update_jacobian))
+        .add_system(cartesian_motion.after(ui_example))
+        .init_resource::<JogSettings>()
+        .init_resource::<SpeedOverride>()
+        .add_system(jog_input.after(ui_example).before(cartesian_motion))
+        .init_resource::<Pendant>()
+        .add_system(show_pendant.after(ui_example).before(cartesian_motion))
+        .init_resource::<PoseLibrary>()
+        .add_startup_system(load_poses)
+        .init_resource::<PoseMove>()
+        .add_system(
+            pose_motion
+                .after(jog_input)
+                .after(show_pendant)
+                .before(run_programs),
+        )
+        .add_system(apply_joint_state.after(run_programs))
+        .init_resource::<Coordination>()
+        .add_system(run_programs.after(cartesian_motion))
+        .add_system(check_zones.after(update_jacobian))
+        .add_system(update_joint_motion.before(update_jacobian))
+        .add_system(compute_torques.after(update_jacobian))
+        .add_system(release_stale_zones.after(run_programs))
+        .init_resource::<GhostPreview>()
+        .init_resource::<GhostMaterials>()
+        .add_system(show_ghosts.after(ui_example).after(update_jacobian))
+        .init_resource::<Selection>()
+        .add_system(pick_objects.before(ui_example).after(update_jacobian))
+        .init_resource::<Manipulator>()
+        .add_startup_system(setup_manipulator)
+        .add_system(manipulate.before(pick_objects).after(update_jacobian))
+        .add_system(draw_manipulator.after(ui_example))
+        .add_system(highlight_selection.after(ui_example))
+        .add_system(update_obstacles.after(ui_example))
+        .init_resource::<GizmoSettings>()
+        .add_startup_system(setup_gizmos)
+        .add_system(draw_gizmos.after(update_jacobian))
+        .add_event::<GridCommand>()
+        .add_system(manage_grids)
+        .add_system(place_grids.after(update_jacobian))
+        .init_resource::<TrailSettings>()
+        .init_resource::<TrailMaterials>()
+        .add_event::<ClearTrails>()
+        .add_system(record_trails.after(update_jacobian))
+        .add_system(draw_trails.after(record_trails))
+        .add_system(record_cycles.after(run_programs).after(compute_torques))
+        .add_system(show_zones)
+        .add_event::<GripperCommand>()
+        .add_system(mount_tool)
+        .add_system(operate_gripper.after(ui_example))
+        .add_system(animate_fingers)
         .add_startup_system(setup_robot)
         .add_startup_system(setup)
+        .add_startup_system(setup_conveyor)
+        .add_startup_system(load_scene_argument)
+        .add_event::<SceneCommand>()
+        .init_resource::<SceneStatus>()
+        .add_system_to_stage(CoreStage::PreUpdate, load_scene)
+        .add_system(save_scene)
+        .add_system(spawn_parts)
+        .add_system(move_parts)
+        .add_system(sink_parts)
+        .add_system(update_sensors)
         .add_system_set(
             SystemSet::new()
-                .with_run_criteria(FixedTimestep::step(TIME_STEP as f64))
-                .with_system(choise_object),
+                .with_run_criteria(FixedTimestep::step(TIME_STEP as f64).with_label(SERVO_TIMESTEP))
+                .with_system(run_servos.after(run_programs)),
         )
         .add_plugin(ObjPlugin)
         .add_plugin(InfiniteGridPlugin)
         .add_plugin(NoCameraPlayerPlugin)
+        .init_resource::<CameraSettings>()
+        .add_event::<CameraCommand>()
+        .init_resource::<CameraBookmarks>()
+        .add_system(camera_commands.after(ui_example))
+        .add_system(orbit_camera)
+        .add_system(animate_camera.after(camera_commands).after(orbit_camera))
+        .add_system(follow_camera.after(update_jacobian))
+        .add_system(switch_fly_camera)
+        .add_system(
+            update_camera
+                .after(animate_camera)
+                .after(follow_camera)
+                .after(switch_fly_camera),
+        )
+        .add_plugin(ViewportPlugin)
+        .init_resource::<ViewportSettings>()
+        .init_resource::<Viewports>()
+        .add_system(show_viewports.after(ui_example))
+        .add_system(update_viewports.after(show_viewports).after(update_camera))
         // Default Movement Settings: sensitivity = 0.00012, speed = 12.0
         .insert_resource(MovementSettings {
             sensitivity: 0.00006,
@@ -53,92 +160,90 @@ fn setup(
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
     // Grid and Axies
-    commands.spawn_bundle(InfiniteGridBundle::default());
+    spawn_grid(&mut commands, "Ground", GridPlacement::Ground);
     // Camera
+    let transform = Transform::from_xyz(4.0, 2.0, 4.0).looking_at(Vec3::ZERO, Vec3::Y);
     commands
         .spawn_bundle(PerspectiveCameraBundle {
-            transform: Transform::from_xyz(4.0, 2.0, 4.0).looking_at(Vec3::ZERO, Vec3::Y),
+            transform,
             ..Default::default()
         })
+        .insert(OrbitCamera::from_transform(
+            &transform,
+            transform.translation.length(),
+        ))
         .insert(FlyCam);
     // Light
-    commands.spawn_bundle(PointLightBundle {
-        point_light: PointLight {
-            intensity: 15000.0,
-            ..default()
+    spawn_light(&mut commands, Vec3::new(-5.0, 8.0, -5.0), 15000.0);
+    spawn_light(&mut commands, Vec3::new(5.0, 8.0, 5.0), 15000.0);
+    // Table with parts to pick up
+    spawn_obstacle(
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        Transform::from_xyz(0.0, 0.35, 2.7),
+        Obstacle {
+            size: Vec3::new(1.2, 0.7, 0.6),
+            color: Color::rgb(0.4, 0.4, 0.45),
         },
-        transform: Transform::from_xyz(-5.0, 8.0, -5.0),
-        ..default()
-    });
-    commands.spawn_bundle(PointLightBundle {
-        point_light: PointLight {
-            intensity: 15000.0,
-            ..default()
-        },
-        transform: Transform::from_xyz(5.0, 8.0, 5.0),
-        ..default()
-    });
+    );
+    for x in [-0.3, 0.3] {
+        spawn_graspable(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            Vec3::new(x, 0.775, 2.7),
+        );
+    }
     // Background
-    commands.spawn_bundle(PbrBundle {
-        mesh: meshes.add(Mesh::from(shape::Capsule {
-            radius: -150.0,
-            depth: -1.0,
-            ..Default::default()
-        })),
-        material: materials.add(StandardMaterial {
-            perceptual_roughness: 1.0,
-            reflectance: 0.0,
-            emissive: Color::rgb(0.0, 0.05, 0.5),
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Capsule {
+                radius: -150.0,
+                depth: -1.0,
+                ..Default::default()
+            })),
+            material: materials.add(StandardMaterial {
+                perceptual_roughness: 1.0,
+                reflectance: 0.0,
+                emissive: Color::rgb(0.0, 0.05, 0.5),
+                ..Default::default()
+            }),
+            transform: Transform::from_xyz(0.0, 0.0, 0.0).with_scale(Vec3::new(1.0, 1.0, 1.0)),
             ..Default::default()
-        }),
-        transform: Transform::from_xyz(0.0, 0.0, 0.0).with_scale(Vec3::new(1.0, 1.0, 1.0)),
-        ..Default::default()
-    });
+        })
+        // Would darken the whole grid otherwise
+        .insert(NotShadowCaster);
 }
 
-fn choise_object(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut query1: Query<&mut ShoulderRotate>,
-    mut query2: Query<&mut LowerArmRotate>,
-    mut query3: Query<&mut ElbowRotate>,
-    mut query4: Query<&mut UpperArmRotate>,
-    mut query5: Query<&mut WristRotate>,
+// Mouse look and WASD only move the camera in fly mode, orbiting needs a free cursor
+fn switch_fly_camera(
+    mut commands: Commands,
+    settings: Res<CameraSettings>,
+    mut windows: ResMut<Windows>,
+    cameras: Query<(Entity, Option<&FlyCam>), With<OrbitCamera>>,
+    mut mode: Local<Option<CameraMode>>,
 ) {
-    let mut object1 = query1.single_mut();
-    let mut object2 = query2.single_mut();
-    let mut object3 = query3.single_mut();
-    let mut object4 = query4.single_mut();
-    let mut object5 = query5.single_mut();
-    if keyboard_input.pressed(KeyCode::Key1) {
-        object1.can_move = true;
-        object2.can_move = false;
-        object3.can_move = false;
-        object4.can_move = false;
-        object5.can_move = false;
-    } else if keyboard_input.pressed(KeyCode::Key2) {
-        object1.can_move = false;
-        object2.can_move = true;
-        object3.can_move = false;
-        object4.can_move = false;
-        object5.can_move = false;
-    } else if keyboard_input.pressed(KeyCode::Key3) {
-        object1.can_move = false;
-        object2.can_move = false;
-        object3.can_move = true;
-        object4.can_move = false;
-        object5.can_move = false;
-    } else if keyboard_input.pressed(KeyCode::Key4) {
-        object1.can_move = false;
-        object2.can_move = false;
-        object3.can_move = false;
-        object4.can_move = true;
-        object5.can_move = false;
-    } else if keyboard_input.pressed(KeyCode::Key5) {
-        object1.can_move = false;
-        object2.can_move = false;
-        object3.can_move = false;
-        object4.can_move = false;
-        object5.can_move = true;
+    if *mode == Some(settings.mode) {
+        return;
+    }
+    *mode = Some(settings.mode);
+    for (entity, fly_cam) in cameras.iter() {
+        match (settings.mode, fly_cam) {
+            (CameraMode::Fly, None) => {
+                commands.entity(entity).insert(FlyCam);
+            }
+            (CameraMode::Orbit, Some(_)) => {
+                commands.entity(entity).remove::<FlyCam>();
+            }
+            _ => {}
+        }
+    }
+    if settings.mode == CameraMode::Orbit {
+        if let Some(window) = windows.get_primary_mut() {
+            window.set_cursor_lock_mode(false);
+            window.set_cursor_visibility(true);
+        }
     }
 }
 
@@ -0,0 +1,413 @@
+use std::f32::consts::TAU;
+
+use bevy::{pbr::NotShadowCaster, prelude::*, render::view::NoFrustumCulling};
+use bevy_egui::EguiContext;
+
+use crate::{
+    camera::OrbitCamera,
+    frames::Frames,
+    picking::{
+        cursor_ray, drag_angle, view_camera, world_to_screen, Picked, Selection, ViewCameras,
+    },
+    robot::Robot,
+    scene::Obstacle,
+    tool::Graspable,
+    trail::line_mesh,
+    viewport::{ViewportCamera, Viewports},
+};
+
+const AXES: [Vec3; 3] = [Vec3::X, Vec3::Y, Vec3::Z];
+const COLORS: [Color; 3] = [Color::RED, Color::GREEN, Color::BLUE];
+// Handle length as a fraction of the camera distance, so it keeps its size on screen
+const HANDLE_SCALE: f32 = 0.15;
+const RING_SEGMENTS: usize = 48;
+// How close in pixels the cursor has to be to grab a handle
+const GRAB_PIXELS: f32 = 8.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManipulatorMode {
+    Translate,
+    Rotate,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct HandleDrag {
+    axis: usize,
+    start: Transform,
+    // Position along the axis where it was grabbed, for translating
+    grabbed: f32,
+    // Turned so far and the cursor of the last frame, for rotating
+    angle: f32,
+    last: Vec2,
+    // Split viewport the handle was grabbed in
+    cell: Option<usize>,
+}
+
+pub struct Manipulator {
+    pub mode: ManipulatorMode,
+    pub snap: bool,
+    // Meters, the ground grid's cell size by default
+    pub translate_step: f32,
+    // Degrees
+    pub angle_step: f32,
+    drag: Option<HandleDrag>,
+}
+
+impl Default for Manipulator {
+    fn default() -> Self {
+        Self {
+            mode: ManipulatorMode::Translate,
+            snap: false,
+            translate_step: 0.1,
+            angle_step: 15.0,
+            drag: None,
+        }
+    }
+}
+
+impl Manipulator {
+    // A handle is held, clicks are not picks then
+    pub fn dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+}
+
+// World-aligned lines of one axis of the translate or rotate handle
+#[derive(Component, Debug)]
+pub struct ManipulatorHandle {
+    axis: usize,
+}
+
+// Robot bases only turn about the vertical, like in the side panel
+fn allowed_axes(mode: ManipulatorMode, robot: bool) -> [bool; 3] {
+    match (mode, robot) {
+        (ManipulatorMode::Rotate, true) => [false, true, false],
+        _ => [true; 3],
+    }
+}
+
+fn handle_segments(
+    mode: ManipulatorMode,
+    origin: Vec3,
+    axis: usize,
+    size: f32,
+) -> Vec<(Vec3, Vec3)> {
+    let direction = AXES[axis];
+    let u = AXES[(axis + 1) % 3];
+    let v = AXES[(axis + 2) % 3];
+    match mode {
+        ManipulatorMode::Translate => {
+            let tip = origin + direction * size;
+            let back = tip - direction * size * 0.15;
+            vec![
+                (origin, tip),
+                (tip, back + u * size * 0.06),
+                (tip, back - u * size * 0.06),
+            ]
+        }
+        ManipulatorMode::Rotate => {
+            let point = |step: usize| {
+                let angle = TAU * step as f32 / RING_SEGMENTS as f32;
+                origin + (u * angle.cos() + v * angle.sin()) * size * 0.8
+            };
+            (0..RING_SEGMENTS)
+                .map(|step| (point(step), point(step + 1)))
+                .collect()
+        }
+    }
+}
+
+fn segment_distance_2d(point: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let ab = b - a;
+    let t = ((point - a).dot(ab) / ab.length_squared().max(f32::EPSILON)).clamp(0.0, 1.0);
+    point.distance(a + ab * t)
+}
+
+// Parameter of the point on the axis line closest to the ray, None when they are parallel
+fn axis_parameter(origin: Vec3, axis: Vec3, ray_origin: Vec3, ray_direction: Vec3) -> Option<f32> {
+    let b = axis.dot(ray_direction);
+    let denominator = 1.0 - b * b;
+    if denominator < 1e-4 {
+        return None;
+    }
+    let w = origin - ray_origin;
+    Some((b * ray_direction.dot(w) - axis.dot(w)) / denominator)
+}
+
+fn snap(value: f32, step: f32) -> f32 {
+    if step > 0.0 {
+        (value / step).round() * step
+    } else {
+        value
+    }
+}
+
+type Movable = (
+    Or<(With<Obstacle>, With<Graspable>, With<Robot>)>,
+    Without<Parent>,
+);
+
+// Transform the handle is drawn at and whether it is a robot base
+fn target_transform(
+    picked: Option<Picked>,
+    frames: &Frames,
+    transforms: &Query<(&mut Transform, Option<&Robot>), Movable>,
+) -> Option<(Transform, bool)> {
+    match picked? {
+        Picked::Object(entity) => transforms
+            .get(entity)
+            .ok()
+            .map(|(transform, robot)| (*transform, robot.is_some())),
+        Picked::Frame(index) if index > 0 => frames
+            .user
+            .get(index)
+            .map(|frame| (frame.transform(), false)),
+        _ => None,
+    }
+}
+
+fn handle_size(
+    camera: &GlobalTransform,
+    projection: Option<&OrthographicProjection>,
+    origin: Vec3,
+) -> f32 {
+    match projection {
+        Some(projection) => projection.scale * HANDLE_SCALE * 2.0,
+        None => camera.translation.distance(origin) * HANDLE_SCALE,
+    }
+}
+
+pub fn setup_manipulator(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (axis, color) in COLORS.into_iter().enumerate() {
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: meshes.add(line_mesh(vec![[0.0; 3]; 2])),
+                material: materials.add(StandardMaterial {
+                    base_color: color,
+                    unlit: true,
+                    ..Default::default()
+                }),
+                visibility: Visibility { is_visible: false },
+                ..Default::default()
+            })
+            .insert(ManipulatorHandle { axis })
+            .insert(NoFrustumCulling)
+            .insert(NotShadowCaster);
+    }
+}
+
+// Grabs a handle under the cursor and moves or turns the selected object or user frame with it,
+// in the main view or any of the split viewports
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn manipulate(
+    mut egui_ctx: ResMut<EguiContext>,
+    buttons: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    viewports: Res<Viewports>,
+    selection: Res<Selection>,
+    mut manipulator: ResMut<Manipulator>,
+    mut frames: ResMut<Frames>,
+    cameras: Query<
+        (
+            &Camera,
+            &GlobalTransform,
+            Option<&OrthographicProjection>,
+            Option<&ViewportCamera>,
+        ),
+        ViewCameras,
+    >,
+    mut transforms: Query<(&mut Transform, Option<&Robot>), Movable>,
+) {
+    if !buttons.pressed(MouseButton::Left) {
+        if manipulator.drag.is_some() {
+            manipulator.drag = None;
+        }
+        return;
+    }
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let cell = manipulator.drag.and_then(|drag| drag.cell);
+    let view = match window
+        .cursor_position()
+        .and_then(|cursor| viewports.locate(window, cursor, cell))
+    {
+        Some(view) => view,
+        None => return,
+    };
+    let view_cameras = || {
+        cameras
+            .iter()
+            .map(|(camera, transform, projection, viewport)| {
+                ((camera, transform, projection), viewport)
+            })
+    };
+    let (camera, camera_transform, _) = match view_camera(view_cameras(), view.cell) {
+        Some(camera) => camera,
+        None => return,
+    };
+    let (current, robot) = match target_transform(selection.picked, &frames, &transforms) {
+        Some(target) => target,
+        None => return,
+    };
+    let mode = manipulator.mode;
+    let origin = current.translation;
+    let cursor = view.position;
+    let (ray_origin, ray_direction) = cursor_ray(camera, camera_transform, view.size, cursor);
+
+    if buttons.just_pressed(MouseButton::Left) {
+        let ctx = egui_ctx.ctx_mut();
+        if ctx.is_pointer_over_area() || ctx.wants_pointer_input() {
+            return;
+        }
+        // The handles are drawn at the size the main camera sees them
+        let size = match view_camera(view_cameras(), None) {
+            Some((_, main_transform, projection)) => {
+                handle_size(main_transform, projection, origin)
+            }
+            None => return,
+        };
+        let screen = |point| world_to_screen(camera, camera_transform, view.size, point);
+        let grabbed = (0..3)
+            .filter(|axis| allowed_axes(mode, robot)[*axis])
+            .filter_map(|axis| {
+                handle_segments(mode, origin, axis, size)
+                    .into_iter()
+                    .filter_map(|(a, b)| Some(segment_distance_2d(cursor, screen(a)?, screen(b)?)))
+                    .reduce(f32::min)
+                    .map(|distance| (distance, axis))
+            })
+            .filter(|(distance, _)| *distance < GRAB_PIXELS)
+            .min_by(|a, b| a.0.total_cmp(&b.0));
+        manipulator.drag = grabbed.map(|(_, axis)| HandleDrag {
+            axis,
+            start: current,
+            grabbed: axis_parameter(origin, AXES[axis], ray_origin, ray_direction).unwrap_or(0.0),
+            angle: 0.0,
+            last: cursor,
+            cell: view.cell,
+        });
+        return;
+    }
+
+    let mut drag = match manipulator.drag {
+        Some(drag) => drag,
+        None => return,
+    };
+    let axis = AXES[drag.axis];
+    let mut target = drag.start;
+    match mode {
+        ManipulatorMode::Translate => {
+            let along =
+                match axis_parameter(drag.start.translation, axis, ray_origin, ray_direction) {
+                    Some(along) => along,
+                    None => return,
+                };
+            target.translation += axis * (along - drag.grabbed);
+            if manipulator.snap {
+                let snapped = snap(target.translation[drag.axis], manipulator.translate_step);
+                target.translation[drag.axis] = snapped;
+            }
+        }
+        ManipulatorMode::Rotate => {
+            drag.angle += drag_angle(
+                camera,
+                camera_transform,
+                view.size,
+                origin,
+                axis,
+                drag.last,
+                cursor,
+            );
+            drag.last = cursor;
+            let angle = if manipulator.snap {
+                snap(drag.angle, manipulator.angle_step.to_radians())
+            } else {
+                drag.angle
+            };
+            target.rotation = Quat::from_axis_angle(axis, angle) * drag.start.rotation;
+        }
+    }
+    manipulator.drag = Some(drag);
+    if target == current {
+        return;
+    }
+    match selection.picked {
+        Some(Picked::Object(entity)) => {
+            if let Ok((mut transform, _)) = transforms.get_mut(entity) {
+                *transform = target;
+            }
+        }
+        Some(Picked::Frame(index)) => {
+            if let Some(frame) = frames.user.get_mut(index) {
+                frame.set_transform(&target);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn draw_manipulator(
+    selection: Res<Selection>,
+    manipulator: Res<Manipulator>,
+    frames: Res<Frames>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    cameras: Query<(&GlobalTransform, Option<&OrthographicProjection>), With<OrbitCamera>>,
+    transforms: Query<(&mut Transform, Option<&Robot>), Movable>,
+    mut handles: Query<(&ManipulatorHandle, &Handle<Mesh>, &mut Visibility)>,
+) {
+    let target = target_transform(selection.picked, &frames, &transforms);
+    for (handle, mesh, mut visibility) in handles.iter_mut() {
+        let lines = match (target, cameras.iter().next()) {
+            (Some((transform, robot)), Some((camera, projection)))
+                if allowed_axes(manipulator.mode, robot)[handle.axis] =>
+            {
+                let origin = transform.translation;
+                let size = handle_size(camera, projection, origin);
+                handle_segments(manipulator.mode, origin, handle.axis, size)
+            }
+            _ => Vec::new(),
+        };
+        let visible = !lines.is_empty();
+        if visibility.is_visible != visible {
+            visibility.is_visible = visible;
+        }
+        if visible {
+            if let Some(mesh) = meshes.get_mut(mesh) {
+                *mesh = line_mesh(
+                    lines
+                        .into_iter()
+                        .flat_map(|(a, b)| [a.to_array(), b.to_array()])
+                        .collect(),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snap_rounds_to_the_nearest_step() {
+        assert!((snap(0.26, 0.1) - 0.3).abs() < 1e-6);
+        assert!((snap(-0.24, 0.1) + 0.2).abs() < 1e-6);
+        assert!(
+            (snap(44.0_f32.to_radians(), 15.0_f32.to_radians()) - 45.0_f32.to_radians()).abs()
+                < 1e-6
+        );
+    }
+
+    #[test]
+    fn snap_without_step_keeps_the_value() {
+        assert_eq!(snap(0.26, 0.0), 0.26);
+        assert_eq!(snap(0.26, -1.0), 0.26);
+    }
+}
@@ -0,0 +1,304 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+
+use crate::{
+    cartesian::{CartesianJog, JogFrame},
+    jog::{jog_axis, JogMode, JogSettings, SpeedOverride},
+    program::{Instruction, Program},
+    robot::{ActiveRobot, JointState, Robot},
+};
+
+// Speed of instructions inserted from the pendant, deg/s
+const INSERT_SPEED: f32 = 60.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PendantTab {
+    Jog,
+    Positions,
+    Program,
+}
+
+impl PendantTab {
+    pub const ALL: [PendantTab; 3] = [PendantTab::Jog, PendantTab::Positions, PendantTab::Program];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PendantTab::Jog => "Jog",
+            PendantTab::Positions => "Positions",
+            PendantTab::Program => "Program",
+        }
+    }
+}
+
+// Virtual teach pendant of the active robot. Motion from the pendant needs the enable switch,
+// releasing it stops the robot like the deadman of a real pendant.
+pub struct Pendant {
+    pub open: bool,
+    pub enabled: bool,
+    pub tab: PendantTab,
+    // Program line the editor and step execution work on
+    pub cursor: usize,
+    // The cursor follows playback started from the pendant
+    follow: bool,
+}
+
+impl Default for Pendant {
+    fn default() -> Self {
+        Self {
+            open: false,
+            enabled: false,
+            tab: PendantTab::Jog,
+            cursor: 0,
+            follow: false,
+        }
+    }
+}
+
+fn status(program: &Program) -> String {
+    match (&program.blocked, program.running) {
+        (Some(reason), _) => format!("Waiting for {}", reason),
+        (None, true) if program.stepping => format!("Step {}", program.current + 1),
+        (None, true) => format!("Running {}", program.current + 1),
+        (None, false) => "Stopped".to_string(),
+    }
+}
+
+// Runs after the side panel, which clears the Cartesian jog, and before the Cartesian motion
+#[allow(clippy::too_many_arguments)]
+pub fn show_pendant(
+    time: Res<Time>,
+    mut egui_ctx: ResMut<EguiContext>,
+    mut pendant: ResMut<Pendant>,
+    mut settings: ResMut<JogSettings>,
+    mut speed_override: ResMut<SpeedOverride>,
+    mut jog: ResMut<CartesianJog>,
+    active: Res<ActiveRobot>,
+    mut robots: Query<(&Robot, &mut Program, &mut JointState)>,
+) {
+    if !pendant.open {
+        return;
+    }
+    let mut open = true;
+    let pendant = &mut *pendant;
+    let robot = active.entity.and_then(|robot| robots.get_mut(robot).ok());
+    egui::Window::new("Teach pendant")
+        .open(&mut open)
+        .default_width(340.0)
+        .show(egui_ctx.ctx_mut(), |ui| {
+            let (robot, mut program, mut joint_state) = match robot {
+                Some(robot) => robot,
+                None => {
+                    ui.label("No active robot");
+                    return;
+                }
+            };
+            ui.horizontal(|ui| {
+                ui.label(format!("{}: {}", robot.name, status(&program)));
+                let text = if pendant.enabled { "Enabled" } else { "Enable" };
+                if ui.selectable_label(pendant.enabled, text).clicked() {
+                    pendant.enabled = !pendant.enabled;
+                    if !pendant.enabled && program.running {
+                        program.stop();
+                    }
+                }
+            });
+            ui.add(egui::Slider::new(&mut speed_override.percent, 1.0..=100.0).text("% override"));
+            ui.horizontal(|ui| {
+                for tab in PendantTab::ALL {
+                    ui.selectable_value(&mut pendant.tab, tab, tab.name());
+                }
+            });
+            ui.separator();
+
+            let enabled = pendant.enabled;
+            let scale = speed_override.scale();
+            match pendant.tab {
+                PendantTab::Jog => {
+                    ui.horizontal(|ui| {
+                        for mode in [JogMode::Joint, JogMode::Cartesian] {
+                            if ui.radio(settings.mode == mode, mode.name()).clicked() {
+                                settings.set_mode(mode);
+                            }
+                        }
+                    });
+                    let mode = settings.mode;
+                    if mode == JogMode::Cartesian {
+                        egui::ComboBox::from_label("Frame")
+                            .selected_text(jog.frame.name())
+                            .show_ui(ui, |ui| {
+                                for frame in JogFrame::ALL {
+                                    ui.selectable_value(&mut jog.frame, frame, frame.name());
+                                }
+                            });
+                    }
+                    ui.add_enabled_ui(enabled, |ui| {
+                        egui::Grid::new("pendant_jog").show(ui, |ui| {
+                            for axis in 0..mode.axis_count() {
+                                let minus = ui.button(" - ").is_pointer_button_down_on();
+                                ui.label(mode.axis_name(axis));
+                                let plus = ui.button(" + ").is_pointer_button_down_on();
+                                ui.end_row();
+                                let direction = plus as i32 - minus as i32;
+                                if direction != 0 {
+                                    jog_axis(
+                                        mode,
+                                        axis,
+                                        direction as f32 * scale,
+                                        time.delta_seconds(),
+                                        &mut jog,
+                                        &mut joint_state,
+                                    );
+                                }
+                            }
+                        });
+                    });
+                    if !enabled {
+                        ui.label("Switch on enable to jog");
+                    }
+                }
+                PendantTab::Positions => {
+                    let current = joint_state.angles.map(f32::to_degrees);
+                    let mut step = None;
+                    let positions = program.instructions.iter_mut().enumerate().filter_map(
+                        |(index, instruction)| match instruction {
+                            Instruction::MoveJoints { joints, .. } => Some((index, joints)),
+                            _ => None,
+                        },
+                    );
+                    for (number, (index, joints)) in positions.enumerate() {
+                        ui.horizontal(|ui| {
+                            ui.label(format!(
+                                "P{} [{:.0} {:.0} {:.0} {:.0} {:.0}]",
+                                number + 1,
+                                joints[0],
+                                joints[1],
+                                joints[2],
+                                joints[3],
+                                joints[4]
+                            ));
+                            if ui.button("Touch up").clicked() {
+                                *joints = current;
+                            }
+                            if ui.add_enabled(enabled, egui::Button::new("Go")).clicked() {
+                                step = Some(index);
+                            }
+                        });
+                    }
+                    if let Some(index) = step {
+                        program.step(index);
+                    }
+                    if ui.button("Store current position").clicked() {
+                        program.instructions.push(Instruction::MoveJoints {
+                            joints: current,
+                            speed: INSERT_SPEED,
+                        });
+                    }
+                }
+                PendantTab::Program => {
+                    let running = program.running;
+                    if pendant.follow {
+                        pendant.cursor = program.current;
+                        pendant.follow = running;
+                    }
+                    let count = program.instructions.len();
+                    pendant.cursor = pendant.cursor.min(count.saturating_sub(1));
+                    egui::ScrollArea::vertical()
+                        .max_height(200.0)
+                        .show(ui, |ui| {
+                            for (index, instruction) in program.instructions.iter().enumerate() {
+                                let marker = if running && index == program.current {
+                                    ">"
+                                } else {
+                                    " "
+                                };
+                                let text =
+                                    format!("{} {}. {}", marker, index + 1, instruction.label());
+                                let selected = count > 0 && index == pendant.cursor;
+                                if ui.selectable_label(selected, text).clicked() {
+                                    pendant.cursor = index;
+                                }
+                            }
+                        });
+                    if count == 0 {
+                        ui.label("Empty program");
+                    }
+
+                    // New lines go below the cursor
+                    let insert = if count == 0 { 0 } else { pendant.cursor + 1 };
+                    let mut inserted = None;
+                    ui.add_enabled_ui(!running, |ui| {
+                        ui.horizontal(|ui| {
+                            if ui.button("MoveJ here").clicked() {
+                                inserted = Some(Instruction::MoveJoints {
+                                    joints: joint_state.angles.map(f32::to_degrees),
+                                    speed: INSERT_SPEED,
+                                });
+                            }
+                            if ui.button("Open").clicked() {
+                                inserted = Some(Instruction::Gripper { close: false });
+                            }
+                            if ui.button("Close").clicked() {
+                                inserted = Some(Instruction::Gripper { close: true });
+                            }
+                            if ui.button("Wait 1 s").clicked() {
+                                inserted = Some(Instruction::Wait { seconds: 1.0 });
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            let cursor = pendant.cursor;
+                            if ui.button("Up").clicked() && cursor > 0 && cursor < count {
+                                program.instructions.swap(cursor, cursor - 1);
+                                pendant.cursor -= 1;
+                            }
+                            if ui.button("Down").clicked() && cursor + 1 < count {
+                                program.instructions.swap(cursor, cursor + 1);
+                                pendant.cursor += 1;
+                            }
+                            if ui.button("Delete").clicked() && cursor < count {
+                                program.instructions.remove(cursor);
+                            }
+                        });
+                    });
+                    if let Some(instruction) = inserted {
+                        program.instructions.insert(insert, instruction);
+                        pendant.cursor = insert;
+                    }
+
+                    ui.add_enabled_ui(enabled, |ui| {
+                        ui.horizontal(|ui| {
+                            if ui
+                                .add_enabled(!running, egui::Button::new("Step"))
+                                .clicked()
+                            {
+                                program.step(pendant.cursor);
+                                pendant.follow = true;
+                            }
+                            if ui.add_enabled(!running, egui::Button::new("Run")).clicked() {
+                                program.start();
+                                pendant.follow = true;
+                            }
+                            if ui.button("Stop").clicked() {
+                                program.stop();
+                            }
+                            ui.checkbox(&mut program.looping, "Loop");
+                        });
+                    });
+                }
+            }
+        });
+    // Closing the pendant lets go of the enable switch
+    if !open {
+        pendant.open = false;
+        if !std::mem::take(&mut pendant.enabled) {
+            return;
+        }
+        if let Some(mut program) = active
+            .entity
+            .and_then(|robot| robots.get_component_mut::<Program>(robot).ok())
+        {
+            if program.running {
+                program.stop();
+            }
+        }
+    }
+}
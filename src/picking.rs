@@ -0,0 +1,340 @@
+use bevy::{
+    prelude::*,
+    render::{camera::Camera, primitives::Aabb},
+};
+use bevy_egui::EguiContext;
+
+use crate::{
+    camera::OrbitCamera,
+    kinematics::Manipulability,
+    manipulator::Manipulator,
+    robot::{ActiveRobot, Joint, JointState, Robot},
+    scene::Obstacle,
+    tool::Graspable,
+    viewport::{ViewportCamera, Viewports},
+};
+
+// Below this the joint axis lies nearly in the screen plane and dragging sideways turns it
+const MIN_AXIS_FACING: f32 = 0.2;
+// Radians per pixel when dragging sideways
+const DRAG_SPEED: f32 = 0.01;
+const HIGHLIGHT: Color = Color::rgb(0.35, 0.25, 0.0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Picked {
+    Link {
+        link: Entity,
+        robot: Entity,
+        index: usize,
+    },
+    // Robot base, obstacle or part
+    Object(Entity),
+    // User frame by index, selected from the side panel to move it with the manipulator
+    Frame(usize),
+}
+
+impl Picked {
+    pub fn entity(&self) -> Option<Entity> {
+        match *self {
+            Picked::Link { link, .. } => Some(link),
+            Picked::Object(entity) => Some(entity),
+            Picked::Frame(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Selection {
+    pub picked: Option<Picked>,
+    // Set on a new pick so the side panel scrolls to the joint slider and jogs that joint
+    pub focus_slider: bool,
+    // Cursor position of the last frame while a link is dragged and the viewport it started in
+    drag: Option<(Vec2, Option<usize>)>,
+}
+
+// Material the selected entity had before it was highlighted
+#[derive(Component, Debug)]
+pub struct Highlighted {
+    original: Handle<StandardMaterial>,
+}
+
+type Pickable = Or<(With<Joint>, With<Robot>, With<Obstacle>, With<Graspable>)>;
+// The main camera and the cameras of the split viewports
+pub type ViewCameras = Or<(With<OrbitCamera>, With<ViewportCamera>)>;
+
+// Camera showing a viewport, the main camera for None
+pub fn view_camera<'a, T>(
+    cameras: impl Iterator<Item = (T, Option<&'a ViewportCamera>)>,
+    cell: Option<usize>,
+) -> Option<T> {
+    cameras
+        .filter(|(_, viewport)| viewport.map(|viewport| viewport.index) == cell)
+        .map(|(camera, _)| camera)
+        .next()
+}
+
+// World ray through a cursor position in a view of the given size, bevy 0.7 cameras have no
+// helper for this
+pub fn cursor_ray(
+    camera: &Camera,
+    transform: &GlobalTransform,
+    size: Vec2,
+    cursor: Vec2,
+) -> (Vec3, Vec3) {
+    let ndc = cursor / size * 2.0 - Vec2::ONE;
+    let ndc_to_world = transform.compute_matrix() * camera.projection_matrix.inverse();
+    // Reversed depth, 1 is the near plane
+    let near = ndc_to_world.project_point3(ndc.extend(1.0));
+    let far = ndc_to_world.project_point3(ndc.extend(0.5));
+    (near, (far - near).normalize())
+}
+
+// Position of a world point in a view of the given size, None when behind the camera
+pub fn world_to_screen(
+    camera: &Camera,
+    transform: &GlobalTransform,
+    size: Vec2,
+    point: Vec3,
+) -> Option<Vec2> {
+    let world_to_ndc = camera.projection_matrix * transform.compute_matrix().inverse();
+    let ndc = world_to_ndc.project_point3(point);
+    (ndc.z > 0.0).then(|| (ndc.truncate() + Vec2::ONE) / 2.0 * size)
+}
+
+// Radians a drag from `last` to `cursor` turns about the axis through `origin`. Turns with the
+// cursor around the origin when looking along the axis, else follows it sideways.
+pub fn drag_angle(
+    camera: &Camera,
+    transform: &GlobalTransform,
+    size: Vec2,
+    origin: Vec3,
+    axis: Vec3,
+    last: Vec2,
+    cursor: Vec2,
+) -> f32 {
+    let facing = axis.dot((transform.translation - origin).normalize_or_zero());
+    match world_to_screen(camera, transform, size, origin) {
+        Some(center) if facing.abs() > MIN_AXIS_FACING => {
+            (last - center).angle_between(cursor - center) * facing.signum()
+        }
+        _ => (cursor.x - last.x) * DRAG_SPEED,
+    }
+}
+
+// Distance along the ray to the bounding box, slab test in the box's own space
+fn ray_hit(origin: Vec3, direction: Vec3, aabb: &Aabb, transform: &GlobalTransform) -> Option<f32> {
+    let inverse = transform.compute_matrix().inverse();
+    let origin = inverse.transform_point3(origin) - Vec3::from(aabb.center);
+    let direction = inverse.transform_vector3(direction);
+    let half = Vec3::from(aabb.half_extents);
+    let mut enter = 0.0_f32;
+    let mut exit = f32::INFINITY;
+    for ((origin, direction), half) in origin
+        .to_array()
+        .into_iter()
+        .zip(direction.to_array())
+        .zip(half.to_array())
+    {
+        if direction.abs() < f32::EPSILON {
+            if origin.abs() > half {
+                return None;
+            }
+            continue;
+        }
+        let a = (-half - origin) / direction;
+        let b = (half - origin) / direction;
+        enter = enter.max(a.min(b));
+        exit = exit.min(a.max(b));
+    }
+    (enter <= exit).then_some(enter)
+}
+
+// Left click picks a link or object under the cursor, dragging a picked link turns its joint.
+// With split viewports the ray is cast from the camera of the viewport clicked in.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn pick_objects(
+    mut egui_ctx: ResMut<EguiContext>,
+    buttons: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    viewports: Res<Viewports>,
+    manipulator: Res<Manipulator>,
+    mut selection: ResMut<Selection>,
+    mut active: ResMut<ActiveRobot>,
+    cameras: Query<(&Camera, &GlobalTransform, Option<&ViewportCamera>), ViewCameras>,
+    targets: Query<(Entity, &Aabb, &GlobalTransform, &Visibility, Option<&Joint>), Pickable>,
+    mut robots: Query<(&Manipulability, &mut JointState)>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    // A grabbed manipulator handle keeps the selection
+    if !buttons.pressed(MouseButton::Left) || manipulator.dragging() {
+        selection.drag = None;
+        return;
+    }
+    let pressed = buttons.just_pressed(MouseButton::Left);
+    let cell = match selection.drag {
+        Some((_, cell)) if !pressed => cell,
+        _ => None,
+    };
+    let view = match window
+        .cursor_position()
+        .and_then(|cursor| viewports.locate(window, cursor, cell))
+    {
+        Some(view) => view,
+        None => return,
+    };
+    let cameras = cameras
+        .iter()
+        .map(|(camera, transform, viewport)| ((camera, transform), viewport));
+    let (camera, camera_transform) = match view_camera(cameras, view.cell) {
+        Some(camera) => camera,
+        None => return,
+    };
+    let cursor = view.position;
+
+    if pressed {
+        let ctx = egui_ctx.ctx_mut();
+        if ctx.is_pointer_over_area() || ctx.wants_pointer_input() {
+            return;
+        }
+        let (origin, direction) = cursor_ray(camera, camera_transform, view.size, cursor);
+        let hit = targets
+            .iter()
+            .filter(|(.., visibility, _)| visibility.is_visible)
+            .filter_map(|(entity, aabb, transform, _, joint)| {
+                ray_hit(origin, direction, aabb, transform)
+                    .map(|distance| (distance, entity, joint))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0));
+        selection.picked = hit.map(|(_, entity, joint)| match joint {
+            Some(joint) => Picked::Link {
+                link: entity,
+                robot: joint.robot,
+                index: joint.index,
+            },
+            None => Picked::Object(entity),
+        });
+        let robot = match selection.picked {
+            Some(Picked::Link { robot, .. }) => Some(robot),
+            Some(Picked::Object(entity)) if robots.contains(entity) => Some(entity),
+            _ => None,
+        };
+        if robot.is_some() && active.entity != robot {
+            active.entity = robot;
+        }
+        selection.focus_slider = matches!(selection.picked, Some(Picked::Link { .. }));
+        selection.drag = Some((cursor, view.cell));
+        return;
+    }
+
+    let (last, robot, index) = match (selection.drag, selection.picked) {
+        (Some((last, _)), Some(Picked::Link { robot, index, .. })) if last != cursor => {
+            (last, robot, index)
+        }
+        _ => return,
+    };
+    selection.drag = Some((cursor, view.cell));
+    let (manipulability, mut joint_state) = match robots.get_mut(robot) {
+        Ok(robot) => robot,
+        Err(_) => return,
+    };
+    let delta = drag_angle(
+        camera,
+        camera_transform,
+        view.size,
+        manipulability.origins[index],
+        manipulability.axes[index],
+        last,
+        cursor,
+    );
+    if delta.is_finite() && delta != 0.0 {
+        let angle = joint_state.angles[index] + delta;
+        joint_state.set(index, angle);
+    }
+}
+
+// Swaps the material of the picked entity for a glowing copy and back when deselected
+pub fn highlight_selection(
+    mut commands: Commands,
+    selection: Res<Selection>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    highlighted: Query<(Entity, &Highlighted)>,
+    mut handles: Query<&mut Handle<StandardMaterial>>,
+) {
+    let selected = selection.picked.and_then(|picked| picked.entity());
+    for (entity, highlight) in highlighted.iter() {
+        if Some(entity) == selected {
+            continue;
+        }
+        if let Ok(mut handle) = handles.get_mut(entity) {
+            // Keeps color edits made while selected
+            let color = materials.get(&*handle).map(|material| material.base_color);
+            if let (Some(color), Some(original)) = (color, materials.get_mut(&highlight.original)) {
+                original.base_color = color;
+            }
+            *handle = highlight.original.clone();
+        }
+        commands.entity(entity).remove::<Highlighted>();
+    }
+
+    let entity = match selected {
+        Some(entity) if !highlighted.contains(entity) => entity,
+        _ => return,
+    };
+    if let Ok(mut handle) = handles.get_mut(entity) {
+        let mut material = match materials.get(&*handle) {
+            Some(material) => material.clone(),
+            None => return,
+        };
+        material.emissive = HIGHLIGHT;
+        let original = std::mem::replace(&mut *handle, materials.add(material));
+        commands.entity(entity).insert(Highlighted { original });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box() -> Aabb {
+        Aabb::from_min_max(Vec3::splat(-0.5), Vec3::splat(0.5))
+    }
+
+    #[test]
+    fn ray_hits_the_near_face() {
+        let transform = GlobalTransform::from_translation(Vec3::new(0.0, 0.0, -5.0));
+        let distance = ray_hit(Vec3::ZERO, -Vec3::Z, &unit_box(), &transform);
+        assert!((distance.unwrap() - 4.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn ray_misses_beside_and_behind() {
+        let transform = GlobalTransform::from_translation(Vec3::new(2.0, 0.0, -5.0));
+        assert_eq!(ray_hit(Vec3::ZERO, -Vec3::Z, &unit_box(), &transform), None);
+        let transform = GlobalTransform::from_translation(Vec3::new(0.0, 0.0, 5.0));
+        assert_eq!(ray_hit(Vec3::ZERO, -Vec3::Z, &unit_box(), &transform), None);
+    }
+
+    #[test]
+    fn ray_respects_box_rotation_and_scale() {
+        // Turned 45 degrees, the corner points at the ray
+        let transform = GlobalTransform::from_translation(Vec3::new(0.0, 0.0, -5.0))
+            .with_rotation(Quat::from_rotation_y(std::f32::consts::FRAC_PI_4))
+            .with_scale(Vec3::splat(2.0));
+        let distance = ray_hit(Vec3::ZERO, -Vec3::Z, &unit_box(), &transform).unwrap();
+        assert!((distance - (5.0 - 2.0_f32.sqrt())).abs() < 1e-4);
+    }
+
+    #[test]
+    fn ray_from_inside_hits_at_zero() {
+        let distance = ray_hit(
+            Vec3::ZERO,
+            Vec3::X,
+            &unit_box(),
+            &GlobalTransform::identity(),
+        );
+        assert_eq!(distance, Some(0.0));
+    }
+}
@@ -0,0 +1,155 @@
+use std::{error::Error, fs};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cartesian::CartesianJog,
+    jog::{JogSettings, SpeedOverride},
+    kinematics::JOINT_COUNT,
+    program::{step_joints, Program},
+    robot::{ActiveRobot, JointState},
+};
+
+pub const POSES_FILE: &str = "poses.ron";
+
+// Joint configuration saved under a name, angles in degrees
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NamedPose {
+    pub name: String,
+    pub joints: [f32; JOINT_COUNT],
+}
+
+impl NamedPose {
+    // Hand-edited files may hold angles a joint move could never reach
+    pub fn validate(&self) -> Result<(), String> {
+        if self.joints.iter().all(|angle| angle.is_finite()) {
+            Ok(())
+        } else {
+            Err(format!("pose {} has an invalid angle", self.name))
+        }
+    }
+}
+
+// Poses shared by all robots, programs refer to them by name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PoseLibrary {
+    pub poses: Vec<NamedPose>,
+}
+
+impl Default for PoseLibrary {
+    fn default() -> Self {
+        Self {
+            poses: vec![NamedPose {
+                name: "HOME".to_string(),
+                joints: [0.0; JOINT_COUNT],
+            }],
+        }
+    }
+}
+
+impl PoseLibrary {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let library: Self = ron::from_str(&fs::read_to_string(path)?)?;
+        library.validate()?;
+        Ok(library)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.poses.iter().try_for_each(NamedPose::validate)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&NamedPose> {
+        self.poses.iter().find(|pose| pose.name == name)
+    }
+
+    // Overwrites a pose of the same name
+    pub fn store(&mut self, name: &str, joints: [f32; JOINT_COUNT]) {
+        match self.poses.iter_mut().find(|pose| pose.name == name) {
+            Some(pose) => pose.joints = joints,
+            None => self.poses.push(NamedPose {
+                name: name.to_string(),
+                joints,
+            }),
+        }
+    }
+}
+
+// Synchronized joint move of the active robot to a recalled pose
+pub struct PoseMove {
+    pub target: Option<[f32; JOINT_COUNT]>,
+    // deg/s of the leading joint
+    pub speed: f32,
+}
+
+impl Default for PoseMove {
+    fn default() -> Self {
+        Self {
+            target: None,
+            speed: 45.0,
+        }
+    }
+}
+
+// Jogging or a running program takes over from the recall
+pub fn pose_motion(
+    time: Res<Time>,
+    mut pose_move: ResMut<PoseMove>,
+    speed_override: Res<SpeedOverride>,
+    jog: Res<CartesianJog>,
+    jog_settings: Res<JogSettings>,
+    active: Res<ActiveRobot>,
+    mut robots: Query<(&Program, &mut JointState)>,
+) {
+    let target = match pose_move.target {
+        Some(target) => target,
+        None => return,
+    };
+    let (program, mut joint_state) = match active.entity.map(|robot| robots.get_mut(robot)) {
+        Some(Ok(robot)) => robot,
+        _ => {
+            pose_move.target = None;
+            return;
+        }
+    };
+    let jogging =
+        jog.linear != Vec3::ZERO || jog.angular != Vec3::ZERO || jog_settings.input != 0.0;
+    if program.running || jogging {
+        pose_move.target = None;
+        return;
+    }
+    let step = (pose_move.speed * speed_override.scale()).to_radians() * time.delta_seconds();
+    if step_joints(&mut joint_state, &target, step) {
+        pose_move.target = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_adds_new_names_and_overwrites_existing_ones() {
+        let mut library = PoseLibrary::default();
+        library.store("pick", [10.0; JOINT_COUNT]);
+        assert_eq!(library.poses.len(), 2);
+        library.store("pick", [20.0; JOINT_COUNT]);
+        assert_eq!(library.poses.len(), 2);
+        assert_eq!(library.get("pick").unwrap().joints, [20.0; JOINT_COUNT]);
+        assert_eq!(library.get("HOME").unwrap().joints, [0.0; JOINT_COUNT]);
+        assert!(library.get("place").is_none());
+    }
+    #[test]
+    fn validate_rejects_invalid_angles() {
+        let mut library = PoseLibrary::default();
+        assert!(library.validate().is_ok());
+        library.store("broken", [0.0, f32::NAN, 0.0, 0.0, 0.0]);
+        assert!(library.validate().is_err());
+    }
+}
@@ -0,0 +1,742 @@
+use std::collections::BTreeMap;
+
+use bevy::{pbr::NotShadowCaster, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    collision::{link_points, segment_hits_box, LINK_RADIUS},
+    conveyor::PresenceSensor,
+    jog::SpeedOverride,
+    kinematics::{Manipulability, JOINT_COUNT},
+    poses::PoseLibrary,
+    robot::{JointState, Robot, JOINT_LIMITS},
+    tool::GripperCommand,
+};
+
+// Joint moves closer than this (radians) to the target are finished
+const MOVE_TOLERANCE: f32 = 1e-4;
+const MAX_MESSAGES: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Instruction {
+    // Synchronized joint move, angles in degrees and speed in deg/s of the leading joint
+    MoveJoints {
+        joints: [f32; JOINT_COUNT],
+        speed: f32,
+    },
+    // Joint move to a pose of the library, looked up by name when it runs
+    MovePose {
+        name: String,
+        speed: f32,
+    },
+    Gripper {
+        close: bool,
+    },
+    Wait {
+        seconds: f32,
+    },
+    SetSignal {
+        name: String,
+        value: bool,
+    },
+    WaitSignal {
+        name: String,
+        value: bool,
+    },
+    WaitSensor {
+        name: String,
+    },
+    // Blocks until no other robot holds the zone, then holds it
+    EnterZone {
+        name: String,
+    },
+    LeaveZone {
+        name: String,
+    },
+}
+
+impl Instruction {
+    pub fn label(&self) -> String {
+        match self {
+            Instruction::MoveJoints { joints, speed } => format!(
+                "MoveJ [{:.0} {:.0} {:.0} {:.0} {:.0}] {:.0} deg/s",
+                joints[0], joints[1], joints[2], joints[3], joints[4], speed
+            ),
+            Instruction::MovePose { name, speed } => format!("MoveJ {} {:.0} deg/s", name, speed),
+            Instruction::Gripper { close: true } => "Close gripper".to_string(),
+            Instruction::Gripper { close: false } => "Open gripper".to_string(),
+            Instruction::Wait { seconds } => format!("Wait {:.1} s", seconds),
+            Instruction::SetSignal { name, value } => format!("Set {} = {}", name, value),
+            Instruction::WaitSignal { name, value } => format!("Wait {} = {}", name, value),
+            Instruction::WaitSensor { name } => format!("Wait sensor {}", name),
+            Instruction::EnterZone { name } => format!("Enter zone {}", name),
+            Instruction::LeaveZone { name } => format!("Leave zone {}", name),
+        }
+    }
+
+    // Time spent in these instructions counts as idle in the cycle report
+    pub fn waits(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Wait { .. }
+                | Instruction::WaitSignal { .. }
+                | Instruction::WaitSensor { .. }
+                | Instruction::EnterZone { .. }
+        )
+    }
+}
+
+// Instruction list of one robot and where its playback is
+#[derive(Component, Debug, Clone, Default)]
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    pub current: usize,
+    pub running: bool,
+    pub looping: bool,
+    // Runs only the current instruction, then stops on the next one
+    pub stepping: bool,
+    // Playback started at the first instruction, reaching the end completes a cycle
+    pub from_start: bool,
+    // Time spent in the current instruction
+    pub elapsed: f32,
+    // What the program is waiting for on another robot, if anything
+    pub blocked: Option<String>,
+    // Instructions finished since the cycle recorder last looked, with the seconds they took
+    pub finished: Vec<(usize, f32)>,
+}
+
+impl Program {
+    pub fn new(instructions: Vec<Instruction>) -> Self {
+        Self {
+            instructions,
+            ..Default::default()
+        }
+    }
+
+    pub fn start(&mut self) {
+        self.current = 0;
+        self.elapsed = 0.0;
+        self.blocked = None;
+        self.finished.clear();
+        self.stepping = false;
+        self.from_start = true;
+        self.running = !self.instructions.is_empty();
+    }
+
+    // Executes the instruction at `index` alone, like the step key of a pendant
+    pub fn step(&mut self, index: usize) {
+        if index >= self.instructions.len() {
+            return;
+        }
+        self.current = index;
+        self.elapsed = 0.0;
+        self.blocked = None;
+        self.finished.clear();
+        self.stepping = true;
+        self.from_start = false;
+        self.running = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.running = false;
+        self.stepping = false;
+        self.blocked = None;
+    }
+
+    fn advance(&mut self) {
+        self.finished.push((self.current, self.elapsed));
+        self.current += 1;
+        self.elapsed = 0.0;
+        self.blocked = None;
+        if self.current >= self.instructions.len() {
+            self.current = 0;
+            self.running = self.looping;
+        }
+        if self.stepping {
+            self.stepping = false;
+            self.running = false;
+        }
+    }
+}
+
+// Box in the world that only one robot may occupy at a time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Zone {
+    pub name: String,
+    pub min: Vec3,
+    pub max: Vec3,
+    #[serde(skip)]
+    pub owner: Option<Entity>,
+    #[serde(skip)]
+    pub occupants: Vec<Entity>,
+}
+
+impl Zone {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            min: Vec3::new(-0.5, 0.0, -0.5),
+            max: Vec3::new(0.5, 2.0, 0.5),
+            owner: None,
+            occupants: Vec::new(),
+        }
+    }
+
+    pub fn violated(&self) -> bool {
+        self.occupants.len() > 1
+            || self
+                .occupants
+                .iter()
+                .any(|robot| matches!(self.owner, Some(owner) if owner != *robot))
+    }
+}
+
+// Signals and zones shared between robot programs, plus what went wrong during playback
+#[derive(Debug, Default)]
+pub struct Coordination {
+    pub signals: BTreeMap<String, bool>,
+    pub zones: Vec<Zone>,
+    pub messages: Vec<String>,
+    pub deadlock: bool,
+}
+
+impl Coordination {
+    pub fn signal(&self, name: &str) -> bool {
+        self.signals.get(name).copied().unwrap_or(false)
+    }
+
+    pub fn report(&mut self, message: String) {
+        self.messages.push(message);
+        if self.messages.len() > MAX_MESSAGES {
+            self.messages.remove(0);
+        }
+    }
+
+    // Zones are released when their owner is gone, e.g. after a stop or removing the robot
+    pub fn release_zones(&mut self, robot: Entity) {
+        for zone in self.zones.iter_mut() {
+            if zone.owner == Some(robot) {
+                zone.owner = None;
+            }
+        }
+    }
+}
+
+enum Step {
+    Next,
+    Busy,
+    Blocked(String),
+    // The instruction cannot run, the program stops with this message
+    Fault(String),
+}
+
+pub fn run_programs(
+    time: Res<Time>,
+    mut coordination: ResMut<Coordination>,
+    mut robots: Query<(Entity, &Robot, &mut Program, &mut JointState)>,
+    sensors: Query<&PresenceSensor>,
+    mut gripper: EventWriter<GripperCommand>,
+    speed_override: Res<SpeedOverride>,
+    poses: Res<PoseLibrary>,
+) {
+    let dt = time.delta_seconds();
+    let speed_override = speed_override.scale();
+    for (entity, _, mut program, mut joint_state) in robots.iter_mut() {
+        if !program.running {
+            continue;
+        }
+        program.elapsed += dt;
+        // Instant instructions run back to back, at most one pass over the program per frame
+        for _ in 0..program.instructions.len() {
+            if program.current >= program.instructions.len() {
+                program.stop();
+                break;
+            }
+            let instruction = program.instructions[program.current].clone();
+            let step = match instruction {
+                Instruction::MoveJoints { joints, speed } => move_joints(
+                    &mut joint_state,
+                    &joints,
+                    speed.to_radians() * speed_override * dt,
+                ),
+                Instruction::MovePose { name, speed } => match poses.get(&name) {
+                    Some(pose) => move_joints(
+                        &mut joint_state,
+                        &pose.joints,
+                        speed.to_radians() * speed_override * dt,
+                    ),
+                    None => Step::Fault(format!("Unknown pose {}", name)),
+                },
+                Instruction::Gripper { close } => {
+                    gripper.send(if close {
+                        GripperCommand::Close(entity)
+                    } else {
+                        GripperCommand::Open(entity)
+                    });
+                    Step::Next
+                }
+                Instruction::Wait { seconds } => {
+                    if program.elapsed >= seconds {
+                        Step::Next
+                    } else {
+                        Step::Busy
+                    }
+                }
+                Instruction::SetSignal { name, value } => {
+                    coordination.signals.insert(name, value);
+                    Step::Next
+                }
+                Instruction::WaitSignal { name, value } => {
+                    if coordination.signal(&name) == value {
+                        Step::Next
+                    } else {
+                        Step::Blocked(format!("signal {} = {}", name, value))
+                    }
+                }
+                Instruction::WaitSensor { name } => {
+                    if sensors
+                        .iter()
+                        .any(|sensor| sensor.name == name && sensor.active)
+                    {
+                        Step::Next
+                    } else {
+                        Step::Busy
+                    }
+                }
+                Instruction::EnterZone { name } => {
+                    match coordination.zones.iter_mut().find(|zone| zone.name == name) {
+                        Some(zone) if zone.owner.is_none() || zone.owner == Some(entity) => {
+                            zone.owner = Some(entity);
+                            Step::Next
+                        }
+                        Some(_) => Step::Blocked(format!("zone {}", name)),
+                        None => Step::Fault(format!("Unknown zone {}", name)),
+                    }
+                }
+                Instruction::LeaveZone { name } => {
+                    for zone in coordination.zones.iter_mut() {
+                        if zone.name == name && zone.owner == Some(entity) {
+                            zone.owner = None;
+                        }
+                    }
+                    Step::Next
+                }
+            };
+            match step {
+                Step::Next => program.advance(),
+                Step::Busy => {
+                    program.blocked = None;
+                    break;
+                }
+                Step::Blocked(reason) => {
+                    program.blocked = Some(reason);
+                    break;
+                }
+                Step::Fault(message) => {
+                    coordination.report(message);
+                    program.stop();
+                    break;
+                }
+            }
+            if !program.running {
+                break;
+            }
+        }
+    }
+
+    let programs: Vec<(Entity, &Program)> = robots
+        .iter()
+        .map(|(entity, _, program, _)| (entity, program))
+        .collect();
+    let stuck = deadlocked(&programs, &coordination);
+    let deadlock = !stuck.is_empty();
+    if deadlock && !coordination.deadlock {
+        let waits: Vec<String> = robots
+            .iter()
+            .filter(|(entity, ..)| stuck.contains(entity))
+            .map(|(_, robot, program, _)| {
+                format!(
+                    "{} waits for {}",
+                    robot.name,
+                    program.blocked.as_deref().unwrap_or("?")
+                )
+            })
+            .collect();
+        coordination.report(format!("Deadlock: {}", waits.join(", ")));
+    }
+    if coordination.deadlock != deadlock {
+        coordination.deadlock = deadlock;
+    }
+}
+
+// Robots a blocked program waits for: the owner of its zone or the running programs that set
+// its signal. None means only the panel can release it.
+fn waits_for(
+    robot: Entity,
+    program: &Program,
+    programs: &[(Entity, &Program)],
+    coordination: &Coordination,
+) -> Vec<Entity> {
+    if !program.running || program.blocked.is_none() {
+        return Vec::new();
+    }
+    match program.instructions.get(program.current) {
+        Some(Instruction::EnterZone { name }) => coordination
+            .zones
+            .iter()
+            .filter(|zone| zone.name == *name)
+            .filter_map(|zone| zone.owner)
+            .filter(|owner| *owner != robot)
+            .collect(),
+        Some(Instruction::WaitSignal { name, value }) => programs
+            .iter()
+            .filter(|(_, other)| {
+                other.running
+                    && other.instructions.iter().any(|instruction| {
+                        matches!(instruction, Instruction::SetSignal { name: set, value: to }
+                            if set == name && to == value)
+                    })
+            })
+            .map(|(entity, _)| *entity)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Robots caught in a cycle of the wait-for relation. A robot waiting for several others is only
+// stuck when all of them are, so robots drop out until every one left waits on the rest.
+fn deadlocked(programs: &[(Entity, &Program)], coordination: &Coordination) -> Vec<Entity> {
+    let waits: Vec<(Entity, Vec<Entity>)> = programs
+        .iter()
+        .map(|(robot, program)| (*robot, waits_for(*robot, program, programs, coordination)))
+        .filter(|(_, targets)| !targets.is_empty())
+        .collect();
+    let mut stuck: Vec<Entity> = waits.iter().map(|(robot, _)| *robot).collect();
+    loop {
+        let left: Vec<Entity> = waits
+            .iter()
+            .filter(|(robot, targets)| {
+                stuck.contains(robot) && targets.iter().all(|target| stuck.contains(target))
+            })
+            .map(|(robot, _)| *robot)
+            .collect();
+        if left.len() == stuck.len() {
+            return stuck;
+        }
+        stuck = left;
+    }
+}
+
+fn move_joints(joint_state: &mut JointState, target: &[f32; JOINT_COUNT], step: f32) -> Step {
+    if step_joints(joint_state, target, step) {
+        Step::Next
+    } else {
+        Step::Busy
+    }
+}
+
+// Moves toward the target angles in degrees by at most `step` radians, true once there
+pub fn step_joints(joint_state: &mut JointState, target: &[f32; JOINT_COUNT], step: f32) -> bool {
+    let mut delta = [0.0; JOINT_COUNT];
+    let mut largest: f32 = 0.0;
+    for (index, angle) in target.iter().enumerate() {
+        let (min, max) = JOINT_LIMITS[index];
+        delta[index] = angle.clamp(min, max).to_radians() - joint_state.angles[index];
+        largest = largest.max(delta[index].abs());
+    }
+    if largest < MOVE_TOLERANCE {
+        return true;
+    }
+    // All joints arrive together, the one with the longest way moves at full speed
+    let fraction = (step / largest).min(1.0);
+    joint_state.add(delta.map(|delta| delta * fraction));
+    false
+}
+
+pub fn check_zones(
+    robots: Query<(Entity, &Robot, &Manipulability)>,
+    mut coordination: ResMut<Coordination>,
+) {
+    let chains: Vec<_> = robots
+        .iter()
+        .map(|(entity, robot, manipulability)| {
+            (entity, robot.name.clone(), link_points(manipulability))
+        })
+        .collect();
+    let margin = Vec3::splat(LINK_RADIUS);
+    let mut violations = Vec::new();
+    for zone in coordination.zones.iter_mut() {
+        let was_violated = zone.violated();
+        let (min, max) = (zone.min - margin, zone.max + margin);
+        zone.occupants = chains
+            .iter()
+            .filter(|(_, _, points)| {
+                points
+                    .windows(2)
+                    .any(|link| segment_hits_box(link[0], link[1], min, max))
+            })
+            .map(|(entity, ..)| *entity)
+            .collect();
+        if zone.violated() && !was_violated {
+            let names: Vec<&str> = chains
+                .iter()
+                .filter(|(entity, ..)| zone.occupants.contains(entity))
+                .map(|(_, name, _)| name.as_str())
+                .collect();
+            violations.push(format!(
+                "Zone {} violated by {}",
+                zone.name,
+                names.join(", ")
+            ));
+        }
+    }
+    for message in violations {
+        coordination.report(message);
+    }
+}
+
+// Releases zones held by programs that were stopped or robots that were removed
+pub fn release_stale_zones(mut coordination: ResMut<Coordination>, programs: Query<&Program>) {
+    let stale: Vec<Entity> = coordination
+        .zones
+        .iter()
+        .filter_map(|zone| zone.owner)
+        .filter(|owner| !matches!(programs.get(*owner), Ok(program) if program.running))
+        .collect();
+    for robot in stale {
+        coordination.release_zones(robot);
+    }
+}
+
+#[derive(Component, Debug)]
+pub struct ZoneMarker;
+
+// Translucent boxes for the zones, rebuilt when a zone is added, removed or resized
+pub fn show_zones(
+    mut commands: Commands,
+    coordination: Res<Coordination>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    markers: Query<Entity, With<ZoneMarker>>,
+    mut shown: Local<Vec<(Vec3, Vec3)>>,
+) {
+    let boxes: Vec<(Vec3, Vec3)> = coordination
+        .zones
+        .iter()
+        .map(|zone| (zone.min, zone.max))
+        .collect();
+    if *shown == boxes {
+        return;
+    }
+    for marker in markers.iter() {
+        commands.entity(marker).despawn_recursive();
+    }
+    let material = materials.add(StandardMaterial {
+        base_color: Color::rgba(1.0, 0.6, 0.1, 0.15),
+        alpha_mode: AlphaMode::Blend,
+        unlit: true,
+        ..Default::default()
+    });
+    for (min, max) in boxes.iter() {
+        let size = (*max - *min).abs();
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: meshes.add(Mesh::from(shape::Box::new(size.x, size.y, size.z))),
+                material: material.clone(),
+                transform: Transform::from_translation((*min + *max) / 2.0),
+                ..Default::default()
+            })
+            .insert(ZoneMarker)
+            .insert(NotShadowCaster);
+    }
+    *shown = boxes;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocked(instructions: Vec<Instruction>, current: usize) -> Program {
+        let mut program = Program::new(instructions);
+        program.start();
+        program.current = current;
+        program.blocked = Some("test".to_string());
+        program
+    }
+
+    fn zone(name: &str, owner: Entity) -> Zone {
+        Zone {
+            owner: Some(owner),
+            ..Zone::new(name)
+        }
+    }
+
+    fn enter(name: &str) -> Instruction {
+        Instruction::EnterZone {
+            name: name.to_string(),
+        }
+    }
+
+    fn wait_signal(name: &str) -> Instruction {
+        Instruction::WaitSignal {
+            name: name.to_string(),
+            value: true,
+        }
+    }
+
+    fn set_signal(name: &str) -> Instruction {
+        Instruction::SetSignal {
+            name: name.to_string(),
+            value: true,
+        }
+    }
+
+    #[test]
+    fn advance_loops_or_stops_at_the_end() {
+        let mut program = Program::new(vec![set_signal("a"), set_signal("b")]);
+        program.start();
+        program.advance();
+        program.advance();
+        assert!(!program.running);
+        assert_eq!(program.current, 0);
+        assert_eq!(program.finished.len(), 2);
+
+        program.looping = true;
+        program.start();
+        program.advance();
+        program.advance();
+        assert!(program.running);
+
+        assert!(program.from_start);
+
+        program.step(1);
+        assert!(!program.from_start);
+        program.advance();
+        assert!(!program.running);
+    }
+
+    #[test]
+    fn robots_holding_each_others_zone_deadlock() {
+        let (a, b, c) = (
+            Entity::from_raw(0),
+            Entity::from_raw(1),
+            Entity::from_raw(2),
+        );
+        let coordination = Coordination {
+            zones: vec![zone("left", a), zone("right", b)],
+            ..Default::default()
+        };
+        let first = blocked(vec![enter("left"), enter("right")], 1);
+        let second = blocked(vec![enter("right"), enter("left")], 1);
+        // The third robot is busy with something else and does not end the deadlock
+        let mut third = Program::new(vec![Instruction::Wait { seconds: 10.0 }]);
+        third.start();
+        let programs = [(a, &first), (b, &second), (c, &third)];
+        assert_eq!(deadlocked(&programs, &coordination), vec![a, b]);
+    }
+
+    #[test]
+    fn robots_waiting_on_each_others_signal_deadlock() {
+        let (a, b) = (Entity::from_raw(0), Entity::from_raw(1));
+        let first = blocked(vec![wait_signal("b_ready"), set_signal("a_ready")], 0);
+        let second = blocked(vec![wait_signal("a_ready"), set_signal("b_ready")], 0);
+        let programs = [(a, &first), (b, &second)];
+        assert_eq!(deadlocked(&programs, &Coordination::default()), vec![a, b]);
+    }
+
+    #[test]
+    fn waiting_on_panel_signals_is_no_deadlock() {
+        let (a, b) = (Entity::from_raw(0), Entity::from_raw(1));
+        let first = blocked(vec![wait_signal("start")], 0);
+        let second = blocked(vec![wait_signal("start")], 0);
+        let programs = [(a, &first), (b, &second)];
+        assert!(deadlocked(&programs, &Coordination::default()).is_empty());
+    }
+
+    // Runs one frame of playback for a single robot
+    fn run_alone(instructions: Vec<Instruction>) -> (World, Entity) {
+        let mut world = World::new();
+        world.insert_resource(Time::default());
+        world.init_resource::<Coordination>();
+        world.init_resource::<SpeedOverride>();
+        world.init_resource::<PoseLibrary>();
+        world.init_resource::<bevy::ecs::event::Events<GripperCommand>>();
+        let mut program = Program::new(instructions);
+        program.start();
+        let robot = world
+            .spawn()
+            .insert(Robot {
+                name: "Robot 1".to_string(),
+            })
+            .insert(program)
+            .insert(JointState::default())
+            .id();
+        SystemStage::single(run_programs).run(&mut world);
+        (world, robot)
+    }
+
+    #[test]
+    fn unknown_zone_stops_the_program() {
+        let (world, robot) = run_alone(vec![enter("cell"), set_signal("done")]);
+        let program = world.get::<Program>(robot).unwrap();
+        assert!(!program.running);
+        assert_eq!(program.current, 0);
+        let coordination = world.get_resource::<Coordination>().unwrap();
+        assert_eq!(coordination.messages, vec!["Unknown zone cell".to_string()]);
+        assert!(!coordination.signal("done"));
+    }
+
+    #[test]
+    fn unknown_pose_stops_the_program() {
+        let move_pose = Instruction::MovePose {
+            name: "place".to_string(),
+            speed: 45.0,
+        };
+        let (world, robot) = run_alone(vec![move_pose, set_signal("done")]);
+        assert!(!world.get::<Program>(robot).unwrap().running);
+        let coordination = world.get_resource::<Coordination>().unwrap();
+        assert_eq!(
+            coordination.messages,
+            vec!["Unknown pose place".to_string()]
+        );
+        assert!(!coordination.signal("done"));
+    }
+
+    #[test]
+    fn step_joints_moves_all_joints_together() {
+        let mut joint_state = JointState::default();
+        let target = [40.0, -20.0, 10.0, 0.0, 5.0];
+        let step = 10.0_f32.to_radians();
+        assert!(!step_joints(&mut joint_state, &target, step));
+        // The joint with the longest way moves by the step, the others in proportion
+        let moved = joint_state.angles.map(f32::to_degrees);
+        let expected = [10.0, -5.0, 2.5, 0.0, 1.25];
+        for (moved, expected) in moved.iter().zip(expected) {
+            assert!((moved - expected).abs() < 1e-3, "{} != {}", moved, expected);
+        }
+    }
+
+    #[test]
+    fn step_joints_arrives_and_reports_it() {
+        let mut joint_state = JointState::default();
+        let target = [40.0, -20.0, 10.0, 0.0, 5.0];
+        let step = 10.0_f32.to_radians();
+        let steps = (0..10)
+            .position(|_| step_joints(&mut joint_state, &target, step))
+            .unwrap();
+        // Four steps to get there and one more to see it is done
+        assert_eq!(steps, 4);
+        for (angle, target) in joint_state.angles.iter().zip(target) {
+            assert!((angle.to_degrees() - target).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn step_joints_stops_at_the_joint_limits() {
+        let mut joint_state = JointState::default();
+        let (_, max) = JOINT_LIMITS[0];
+        let target = [max + 90.0, 0.0, 0.0, 0.0, 0.0];
+        while !step_joints(&mut joint_state, &target, 1.0) {}
+        assert!((joint_state.angles[0].to_degrees() - max).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,287 @@
+use std::{error::Error, fmt::Write, fs, path::Path};
+
+use bevy::prelude::*;
+use serde::Serialize;
+
+use crate::{
+    cartesian::CartesianMove,
+    dynamics::{JointMotion, JointTorques},
+    kinematics::JOINT_COUNT,
+    program::Program,
+    robot::{ActiveRobot, Robot, JOINT_NAMES},
+};
+
+pub const DEFAULT_REPORT_FILE: &str = "cycle_report.md";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum CycleSource {
+    Program,
+    LinearMove,
+}
+
+impl CycleSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CycleSource::Program => "Program",
+            CycleSource::LinearMove => "Linear move",
+        }
+    }
+}
+
+// One instruction of the cycle, or the whole move for a linear move
+#[derive(Debug, Clone, Serialize)]
+pub struct Segment {
+    pub index: usize,
+    pub instruction: String,
+    pub duration: f32,
+    pub idle: bool,
+}
+
+// Times in seconds, energies in J, peaks in deg/s and deg/s²
+#[derive(Debug, Clone, Serialize)]
+pub struct CycleReport {
+    pub robot: String,
+    pub source: CycleSource,
+    // False when the program was stopped before the end of the cycle
+    pub complete: bool,
+    pub cycle_time: f32,
+    pub idle_time: f32,
+    // Positive mechanical work of the motors, and the work they absorbed while braking
+    pub energy: f32,
+    pub regenerated: f32,
+    pub peak_velocity: [f32; JOINT_COUNT],
+    pub peak_acceleration: [f32; JOINT_COUNT],
+    pub segments: Vec<Segment>,
+}
+
+impl CycleReport {
+    fn new(robot: &str, source: CycleSource) -> Self {
+        Self {
+            robot: robot.to_string(),
+            source,
+            complete: false,
+            cycle_time: 0.0,
+            idle_time: 0.0,
+            energy: 0.0,
+            regenerated: 0.0,
+            peak_velocity: [0.0; JOINT_COUNT],
+            peak_acceleration: [0.0; JOINT_COUNT],
+            segments: Vec::new(),
+        }
+    }
+
+    fn sample(&mut self, dt: f32, motion: &JointMotion, torques: &JointTorques) {
+        self.cycle_time += dt;
+        for (index, (velocity, acceleration)) in
+            motion.velocity.iter().zip(motion.acceleration).enumerate()
+        {
+            let power = torques.current[index] * velocity;
+            if power > 0.0 {
+                self.energy += power * dt;
+            } else {
+                self.regenerated -= power * dt;
+            }
+            let peak = &mut self.peak_velocity[index];
+            *peak = peak.max(velocity.abs().to_degrees());
+            let peak = &mut self.peak_acceleration[index];
+            *peak = peak.max(acceleration.abs().to_degrees());
+        }
+    }
+
+    fn push(&mut self, segment: Segment) {
+        if segment.idle {
+            self.idle_time += segment.duration;
+        }
+        self.segments.push(segment);
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut text = String::new();
+        // Writing to a String cannot fail
+        let _ = writeln!(
+            text,
+            "# Cycle report: {} ({})\n",
+            self.robot,
+            self.source.name()
+        );
+        if !self.complete {
+            let _ = writeln!(text, "Stopped before the end of the cycle.\n");
+        }
+        let _ = writeln!(text, "| Quantity | Value |");
+        let _ = writeln!(text, "| --- | ---: |");
+        let _ = writeln!(text, "| Cycle time | {:.2} s |", self.cycle_time);
+        let _ = writeln!(text, "| Idle time | {:.2} s |", self.idle_time);
+        let _ = writeln!(text, "| Mechanical energy | {:.1} J |", self.energy);
+        let _ = writeln!(
+            text,
+            "| Absorbed while braking | {:.1} J |",
+            self.regenerated
+        );
+        let _ = writeln!(
+            text,
+            "\n| Joint | Peak velocity (deg/s) | Peak acceleration (deg/s²) |"
+        );
+        let _ = writeln!(text, "| --- | ---: | ---: |");
+        for (index, name) in JOINT_NAMES.iter().enumerate() {
+            let _ = writeln!(
+                text,
+                "| {} | {:.1} | {:.1} |",
+                name, self.peak_velocity[index], self.peak_acceleration[index]
+            );
+        }
+        let _ = writeln!(text, "\n| # | Instruction | Duration (s) | Idle |");
+        let _ = writeln!(text, "| ---: | --- | ---: | --- |");
+        for segment in self.segments.iter() {
+            let _ = writeln!(
+                text,
+                "| {} | {} | {:.2} | {} |",
+                segment.index + 1,
+                segment.instruction,
+                segment.duration,
+                if segment.idle { "yes" } else { "" }
+            );
+        }
+        text
+    }
+
+    // Markdown for `.md` files, JSON otherwise
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let text = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("md") => self.to_markdown(),
+            _ => self.to_json()?,
+        };
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+// Records a report while a program or a linear move of the robot runs and keeps the last one
+#[derive(Component, Debug, Clone, Default)]
+pub struct CycleRecorder {
+    pub recording: Option<CycleReport>,
+    pub last: Option<CycleReport>,
+}
+
+pub fn record_cycles(
+    time: Res<Time>,
+    active: Res<ActiveRobot>,
+    cartesian_move: Res<CartesianMove>,
+    mut robots: Query<(
+        Entity,
+        &Robot,
+        &mut Program,
+        &JointMotion,
+        &JointTorques,
+        &mut CycleRecorder,
+    )>,
+) {
+    let dt = time.delta_seconds();
+    for (entity, robot, mut program, motion, torques, mut recorder) in robots.iter_mut() {
+        // Only touch the program when there is something to take, it is change detected
+        let finished = if program.finished.is_empty() {
+            Vec::new()
+        } else {
+            std::mem::take(&mut program.finished)
+        };
+        let linear_move = active.entity == Some(entity) && cartesian_move.target.is_some();
+        if recorder.recording.is_none() {
+            if program.running || !finished.is_empty() {
+                recorder.recording = Some(CycleReport::new(&robot.name, CycleSource::Program));
+            } else if linear_move {
+                recorder.recording = Some(CycleReport::new(&robot.name, CycleSource::LinearMove));
+            } else {
+                continue;
+            }
+        }
+        let recorder = &mut *recorder;
+        let report = match recorder.recording.as_mut() {
+            Some(report) => report,
+            None => continue,
+        };
+        report.sample(dt, motion, torques);
+
+        let done = if report.source == CycleSource::Program {
+            let mut done = !program.running;
+            for (index, duration) in finished {
+                let instruction = match program.instructions.get(index) {
+                    Some(instruction) => instruction,
+                    None => continue,
+                };
+                report.push(Segment {
+                    index,
+                    instruction: instruction.label(),
+                    duration,
+                    idle: instruction.waits(),
+                });
+                // The last instruction closes the cycle, a looping program starts the next one.
+                // Stepping through it from the pendant is no cycle.
+                if index + 1 == program.instructions.len() {
+                    report.complete = program.from_start;
+                    done = true;
+                }
+            }
+            done
+        } else if !linear_move {
+            report.push(Segment {
+                index: 0,
+                instruction: CycleSource::LinearMove.name().to_string(),
+                duration: report.cycle_time,
+                idle: false,
+            });
+            // Jogging, Stop or switching robots cancel the move before it gets there
+            report.complete = cartesian_move.reached;
+            true
+        } else {
+            false
+        };
+        if done {
+            recorder.last = recorder.recording.take();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> CycleReport {
+        let mut report = CycleReport::new("Robot 1", CycleSource::Program);
+        report.complete = true;
+        report.cycle_time = 3.5;
+        report.push(Segment {
+            index: 0,
+            instruction: "MoveJ".to_string(),
+            duration: 2.0,
+            idle: false,
+        });
+        report.push(Segment {
+            index: 1,
+            instruction: "Wait 1.5 s".to_string(),
+            duration: 1.5,
+            idle: true,
+        });
+        report
+    }
+
+    #[test]
+    fn idle_segments_add_up() {
+        assert!((report().idle_time - 1.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn json_holds_the_whole_report() {
+        let json: serde_json::Value = serde_json::from_str(&report().to_json().unwrap()).unwrap();
+        assert_eq!(json["robot"], "Robot 1");
+        assert_eq!(json["source"], "Program");
+        assert_eq!(json["complete"], true);
+        assert_eq!(json["peak_velocity"].as_array().unwrap().len(), JOINT_COUNT);
+        let segments = json["segments"].as_array().unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[1]["instruction"], "Wait 1.5 s");
+        assert_eq!(segments[1]["idle"], true);
+    }
+}
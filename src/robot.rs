@@ -1,110 +1,305 @@
-use bevy::prelude::*;
+use bevy::{math::const_vec3, prelude::*};
 
 use crate::{
-    elbow::ElbowRotate, lower_arm::LowerArmRotate, shoulder::ShoulderRotate,
-    upper_arm::UpperArmRotate, wrist::WristRotate,
+    dynamics::{JointMotion, JointTorques, Payload, PayloadLoad, RobotDynamics},
+    kinematics::{Manipulability, JOINT_COUNT},
+    program::Program,
+    report::CycleRecorder,
+    servo::ServoControl,
+    tool::ToolSettings,
+    trail::Trail,
 };
 
+// Distance between robots added from the side panel
+const ROBOT_SPACING: f32 = 3.5;
+
+// Tool mounting face at the end of the wrist link, in the wrist frame
+pub const FLANGE_OFFSET: Vec3 = const_vec3!([0.0, 0.0, 0.65]);
+
+pub const JOINT_NAMES: [&str; JOINT_COUNT] =
+    ["Shoulder", "Lower Arm", "Elbow", "Upper Arm", "Wrist"];
+
+// Joint limits in degrees
+pub const JOINT_LIMITS: [(f32, f32); JOINT_COUNT] = [
+    (-180.0, 180.0),
+    (-40.0, 90.0),
+    (-55.0, 10.0),
+    (-20.0, 40.0),
+    (-180.0, 180.0),
+];
+
+// Position of a link in the chain and the axis it rotates about, given in the parent frame.
+// `home` is the link rotation at zero angle.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Joint {
+    pub robot: Entity,
+    pub index: usize,
+    pub axis: Vec3,
+    pub home: Quat,
+}
+
+// Base of a robot instance, carries the joint state, tool settings and kinematics of that robot
+#[derive(Component, Debug, Clone)]
+pub struct Robot {
+    pub name: String,
+}
+
+// Joint angles in radians, the link transforms follow these
+#[derive(Component, Debug, Clone, Default, PartialEq)]
+pub struct JointState {
+    pub angles: [f32; JOINT_COUNT],
+}
+
+impl JointState {
+    pub fn set(&mut self, index: usize, angle: f32) {
+        let (min, max) = JOINT_LIMITS[index];
+        self.angles[index] = angle.clamp(min.to_radians(), max.to_radians());
+    }
+
+    pub fn add(&mut self, delta: [f32; JOINT_COUNT]) {
+        for (index, delta) in delta.iter().enumerate() {
+            self.set(index, self.angles[index] + delta);
+        }
+    }
+}
+
+// Robot that the side panel, jogging and gripper buttons act on
+#[derive(Default)]
+pub struct ActiveRobot {
+    pub entity: Option<Entity>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum RobotCommand {
+    Add,
+    Remove(Entity),
+}
+
 pub fn setup_robot(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
-    let rotation_speed: f32 = f32::to_radians(1.);
+    spawn_robot(
+        &mut commands,
+        &asset_server,
+        &mut materials,
+        "Robot 1",
+        Transform::identity(),
+    );
+}
+
+// Spawns the base with the whole arm as its children, moving the base moves the robot
+pub fn spawn_robot(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    materials: &mut Assets<StandardMaterial>,
+    name: &str,
+    base: Transform,
+) -> Entity {
     let material = materials.add(Color::rgb(0.8, 0.7, 0.6).into());
-    commands.spawn_bundle(PbrBundle {
-        mesh: asset_server.load("models/Gleb_Robot/base.obj"),
-        material: material.clone(),
-        transform: Transform::from_xyz(0.0, 0.0, 0.0),
-        ..Default::default()
-    });
-    commands
+    let robot = commands
         .spawn_bundle(PbrBundle {
-            mesh: asset_server.load("models/Gleb_Robot/shoulder.obj"),
+            mesh: asset_server.load("models/Gleb_Robot/base.obj"),
             material: material.clone(),
-            transform: Transform::from_matrix(Mat4::from_scale_rotation_translation(
-                Vec3::new(1., 1., 1.),
-                Quat::from_rotation_x(0.),
-                Vec3::new(0.0, 0., 0.),
-            )),
+            transform: base,
             ..Default::default()
         })
-        .insert(ShoulderRotate {
-            rotation_speed,
-            can_move: false,
+        .insert(Robot {
+            name: name.to_string(),
         })
-        .with_children(|parent| {
-            parent
-                .spawn_bundle(PbrBundle {
-                    mesh: asset_server.load("models/Gleb_Robot/lower_arm.obj"),
-                    material: material.clone(),
-                    transform: Transform::from_matrix(Mat4::from_scale_rotation_translation(
-                        Vec3::new(1., 1., 1.),
-                        Quat::from_rotation_y(0.),
-                        Vec3::new(0., 0.8, 0.25),
-                    )),
-                    ..Default::default()
-                })
-                .insert(LowerArmRotate {
-                    rotation_speed,
-                    can_move: false,
-                })
-                .with_children(|parent| {
-                    parent
-                        .spawn_bundle(PbrBundle {
-                            mesh: asset_server.load("models/Gleb_Robot/elbow.obj"),
-                            material: material.clone(),
-                            transform: Transform::from_matrix(
-                                Mat4::from_scale_rotation_translation(
-                                    Vec3::new(1., 1., 1.),
-                                    Quat::from_rotation_y(0.),
-                                    Vec3::new(0.0, 0.7379941, -1.5010117),
+        .insert(JointState::default())
+        .insert(ToolSettings::default())
+        .insert(Manipulability::default())
+        .insert(Program::default())
+        .insert(RobotDynamics::default())
+        .insert(JointMotion::default())
+        .insert(JointTorques::default())
+        .insert(Payload::default())
+        .insert(PayloadLoad::default())
+        .insert(ServoControl::default())
+        .insert(CycleRecorder::default())
+        .insert(Trail::default())
+        .id();
+    commands.entity(robot).with_children(|parent| {
+        parent
+            .spawn_bundle(PbrBundle {
+                mesh: asset_server.load("models/Gleb_Robot/shoulder.obj"),
+                material: material.clone(),
+                transform: Transform::from_matrix(Mat4::from_scale_rotation_translation(
+                    Vec3::new(1., 1., 1.),
+                    Quat::from_rotation_x(0.),
+                    Vec3::new(0.0, 0., 0.),
+                )),
+                ..Default::default()
+            })
+            .insert(Joint {
+                robot,
+                index: 0,
+                axis: Vec3::Y,
+                home: Quat::IDENTITY,
+            })
+            .with_children(|parent| {
+                parent
+                    .spawn_bundle(PbrBundle {
+                        mesh: asset_server.load("models/Gleb_Robot/lower_arm.obj"),
+                        material: material.clone(),
+                        transform: Transform::from_matrix(Mat4::from_scale_rotation_translation(
+                            Vec3::new(1., 1., 1.),
+                            Quat::from_rotation_y(0.),
+                            Vec3::new(0., 0.8, 0.25),
+                        )),
+                        ..Default::default()
+                    })
+                    .insert(Joint {
+                        robot,
+                        index: 1,
+                        axis: Vec3::X,
+                        home: Quat::IDENTITY,
+                    })
+                    .with_children(|parent| {
+                        parent
+                            .spawn_bundle(PbrBundle {
+                                mesh: asset_server.load("models/Gleb_Robot/elbow.obj"),
+                                material: material.clone(),
+                                transform: Transform::from_matrix(
+                                    Mat4::from_scale_rotation_translation(
+                                        Vec3::new(1., 1., 1.),
+                                        Quat::from_rotation_y(0.),
+                                        Vec3::new(0.0, 0.7379941, -1.5010117),
+                                    ),
                                 ),
-                            ),
-                            ..Default::default()
-                        })
-                        .insert(ElbowRotate {
-                            rotation_speed,
-                            can_move: false,
-                        })
-                        .with_children(|parent| {
-                            parent
-                                .spawn_bundle(PbrBundle {
-                                    mesh: asset_server.load("models/Gleb_Robot/upper_arm.obj"),
-                                    material: material.clone(),
-                                    transform: Transform::from_matrix(
-                                        Mat4::from_scale_rotation_translation(
-                                            Vec3::new(1., 1., 1.),
-                                            Quat::from_rotation_x(0.),
-                                            Vec3::new(0.0, 0.9099869, 1.7750295),
+                                ..Default::default()
+                            })
+                            .insert(Joint {
+                                robot,
+                                index: 2,
+                                axis: Vec3::X,
+                                home: Quat::IDENTITY,
+                            })
+                            .with_children(|parent| {
+                                parent
+                                    .spawn_bundle(PbrBundle {
+                                        mesh: asset_server.load("models/Gleb_Robot/upper_arm.obj"),
+                                        material: material.clone(),
+                                        transform: Transform::from_matrix(
+                                            Mat4::from_scale_rotation_translation(
+                                                Vec3::new(1., 1., 1.),
+                                                Quat::from_rotation_x(0.),
+                                                Vec3::new(0.0, 0.9099869, 1.7750295),
+                                            ),
                                         ),
-                                    ),
-                                    ..Default::default()
-                                })
-                                .insert(UpperArmRotate {
-                                    rotation_speed,
-                                    can_move: false,
-                                })
-                                .with_children(|parent| {
-                                    parent
-                                        .spawn_bundle(PbrBundle {
-                                            mesh: asset_server.load("models/Gleb_Robot/wrist.obj"),
-                                            material: material.clone(),
-                                            transform: Transform::from_matrix(
-                                                Mat4::from_scale_rotation_translation(
-                                                    Vec3::new(1., 1., 1.),
-                                                    Quat::from_rotation_x(0.25844246),
-                                                    Vec3::new(0.0, -0.09199781, 0.33600545),
+                                        ..Default::default()
+                                    })
+                                    .insert(Joint {
+                                        robot,
+                                        index: 3,
+                                        axis: Vec3::X,
+                                        home: Quat::IDENTITY,
+                                    })
+                                    .with_children(|parent| {
+                                        parent
+                                            .spawn_bundle(PbrBundle {
+                                                mesh: asset_server
+                                                    .load("models/Gleb_Robot/wrist.obj"),
+                                                material: material.clone(),
+                                                transform: Transform::from_matrix(
+                                                    Mat4::from_scale_rotation_translation(
+                                                        Vec3::new(1., 1., 1.),
+                                                        Quat::from_rotation_x(0.25844246),
+                                                        Vec3::new(0.0, -0.09199781, 0.33600545),
+                                                    ),
                                                 ),
-                                            ),
-                                            ..Default::default()
-                                        })
-                                        .insert(WristRotate {
-                                            rotation_speed,
-                                            can_move: false,
-                                        });
-                                });
-                        });
-                });
-        });
+                                                ..Default::default()
+                                            })
+                                            .insert(Joint {
+                                                robot,
+                                                index: 4,
+                                                axis: Vec3::Z,
+                                                home: Quat::from_rotation_x(0.25844246),
+                                            });
+                                    });
+                            });
+                    });
+            });
+    });
+    robot
+}
+
+// Robots whose links have to be moved
+type MovedRobot = Or<(Changed<JointState>, Changed<ServoControl>)>;
+
+// In dynamic mode the links follow the servo positions instead of the commanded angles
+pub fn apply_joint_state(
+    robots: Query<(&JointState, &ServoControl), MovedRobot>,
+    mut joints: Query<(&Joint, &mut Transform)>,
+) {
+    for (joint, mut transform) in joints.iter_mut() {
+        if let Ok((state, servo)) = robots.get(joint.robot) {
+            let angle = servo.angles(state)[joint.index];
+            transform.rotation = Quat::from_axis_angle(joint.axis, angle) * joint.home;
+        }
+    }
+}
+
+// Falls back to the first robot when the active one is missing or was removed
+pub fn select_active_robot(mut active: ResMut<ActiveRobot>, robots: Query<Entity, With<Robot>>) {
+    let valid = matches!(active.entity, Some(entity) if robots.contains(entity));
+    if !valid {
+        let first = robots.iter().next();
+        if active.entity != first {
+            active.entity = first;
+        }
+    }
+}
+
+pub fn manage_robots(
+    mut commands: Commands,
+    mut events: EventReader<RobotCommand>,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut active: ResMut<ActiveRobot>,
+    robots: Query<(Entity, &Robot, &Transform)>,
+) {
+    // Commands are applied after this system, so robots added or removed here are tracked
+    let mut bases: Vec<(Entity, String, Vec3)> = robots
+        .iter()
+        .map(|(entity, robot, transform)| (entity, robot.name.clone(), transform.translation))
+        .collect();
+    for command in events.iter() {
+        match *command {
+            RobotCommand::Add => {
+                let mut number = bases.len() + 1;
+                while bases
+                    .iter()
+                    .any(|(_, name, _)| *name == format!("Robot {}", number))
+                {
+                    number += 1;
+                }
+                // First place along X that no base is close to
+                let position = (0..)
+                    .map(|slot| Vec3::new(ROBOT_SPACING * slot as f32, 0.0, 0.0))
+                    .find(|position| {
+                        bases
+                            .iter()
+                            .all(|(.., base)| base.distance(*position) >= ROBOT_SPACING * 0.5)
+                    })
+                    .unwrap_or_default();
+                let name = format!("Robot {}", number);
+                let robot = spawn_robot(
+                    &mut commands,
+                    &asset_server,
+                    &mut materials,
+                    &name,
+                    Transform::from_translation(position),
+                );
+                bases.push((robot, name, position));
+                active.entity = Some(robot);
+            }
+            RobotCommand::Remove(robot) => {
+                bases.retain(|(entity, ..)| *entity != robot);
+                commands.entity(robot).despawn_recursive();
+            }
+        }
+    }
 }
@@ -0,0 +1,418 @@
+use std::{error::Error, fs};
+
+use bevy::{prelude::*, render::primitives::Aabb};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::{CameraBookmark, CameraBookmarks, OrbitCamera},
+    conveyor::Part,
+    dynamics::{Payload, RobotDynamics},
+    frames::Frames,
+    kinematics::JOINT_COUNT,
+    poses::{NamedPose, PoseLibrary},
+    program::{Coordination, Instruction, Program, Zone},
+    robot::{spawn_robot, JointState, Robot},
+    servo::{ServoControl, ServoTuning},
+    tool::{Graspable, ToolSettings},
+};
+
+pub const DEFAULT_SCENE_FILE: &str = "scene.ron";
+
+// Static box in the cell, e.g. a table or a fence
+#[derive(Component, Debug, Clone)]
+pub struct Obstacle {
+    pub size: Vec3,
+    pub color: Color,
+}
+
+// Position plus XYZ Euler angles in degrees, easier to edit by hand than a quaternion
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Pose {
+    pub translation: Vec3,
+    pub rotation: Vec3,
+}
+
+impl From<&Transform> for Pose {
+    fn from(transform: &Transform) -> Self {
+        let (x, y, z) = transform.rotation.to_euler(EulerRot::XYZ);
+        Self {
+            translation: transform.translation,
+            rotation: Vec3::new(x.to_degrees(), y.to_degrees(), z.to_degrees()),
+        }
+    }
+}
+
+impl Pose {
+    pub fn transform(&self) -> Transform {
+        Transform {
+            translation: self.translation,
+            rotation: Quat::from_euler(
+                EulerRot::XYZ,
+                self.rotation.x.to_radians(),
+                self.rotation.y.to_radians(),
+                self.rotation.z.to_radians(),
+            ),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RobotEntry {
+    #[serde(default)]
+    pub name: String,
+    pub base: Pose,
+    // Degrees
+    pub joints: [f32; JOINT_COUNT],
+    pub tool: ToolSettings,
+    #[serde(default)]
+    pub program: Vec<Instruction>,
+    #[serde(default)]
+    pub dynamics: RobotDynamics,
+    #[serde(default)]
+    pub payload: Payload,
+    #[serde(default)]
+    pub servo: ServoTuning,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObstacleEntry {
+    pub pose: Pose,
+    pub size: Vec3,
+    pub color: Color,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightEntry {
+    pub position: Vec3,
+    pub intensity: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneFile {
+    pub robots: Vec<RobotEntry>,
+    pub obstacles: Vec<ObstacleEntry>,
+    pub parts: Vec<Vec3>,
+    pub frames: Frames,
+    pub lights: Vec<LightEntry>,
+    pub camera: Pose,
+    #[serde(default)]
+    pub zones: Vec<Zone>,
+    #[serde(default)]
+    pub bookmarks: Vec<CameraBookmark>,
+    #[serde(default)]
+    pub poses: Vec<NamedPose>,
+}
+
+impl SceneFile {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let scene: Self = ron::from_str(&fs::read_to_string(path)?)?;
+        scene.poses.iter().try_for_each(NamedPose::validate)?;
+        Ok(scene)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+// Parts placed in the scene, not the ones fed by the conveyor or held by a tool
+type ScenePart = (With<Graspable>, Without<Part>, Without<Parent>);
+
+// Parts saved with the scene, a held part is saved where the tool holds it
+type SavedPart = (With<Graspable>, Without<Part>);
+
+// Everything a scene file describes, despawned before another scene is loaded
+type SceneObject = Or<(With<Robot>, With<Obstacle>, ScenePart, With<PointLight>)>;
+
+#[derive(Debug, Clone)]
+pub enum SceneCommand {
+    Load(String),
+    Save(String),
+}
+
+// Result of the last load or save, shown in the side panel
+#[derive(Default)]
+pub struct SceneStatus {
+    pub message: String,
+}
+
+// Path given with `--scene <file>` on the command line
+pub fn scene_argument() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--scene" {
+            return args.next();
+        }
+        if let Some(path) = arg.strip_prefix("--scene=") {
+            return Some(path.to_string());
+        }
+    }
+    None
+}
+
+pub fn load_scene_argument(mut events: EventWriter<SceneCommand>) {
+    if let Some(path) = scene_argument() {
+        events.send(SceneCommand::Load(path));
+    }
+}
+
+pub fn spawn_obstacle(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    transform: Transform,
+    obstacle: Obstacle,
+) -> Entity {
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Box::new(
+                obstacle.size.x,
+                obstacle.size.y,
+                obstacle.size.z,
+            ))),
+            material: materials.add(obstacle.color.into()),
+            transform,
+            ..Default::default()
+        })
+        .insert(obstacle)
+        .id()
+}
+
+// Rebuilds the box and recolors it when an obstacle is edited in the side panel
+#[allow(clippy::type_complexity)]
+pub fn update_obstacles(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    obstacles: Query<
+        (Entity, &Obstacle, &Handle<Mesh>, &Handle<StandardMaterial>),
+        Changed<Obstacle>,
+    >,
+) {
+    for (entity, obstacle, mesh, material) in obstacles.iter() {
+        if let Some(mesh) = meshes.get_mut(mesh) {
+            *mesh = Mesh::from(shape::Box::new(
+                obstacle.size.x,
+                obstacle.size.y,
+                obstacle.size.z,
+            ));
+            // Recomputed by bevy for the new size
+            commands.entity(entity).remove::<Aabb>();
+        }
+        if let Some(material) = materials.get_mut(material) {
+            if material.base_color != obstacle.color {
+                material.base_color = obstacle.color;
+            }
+        }
+    }
+}
+
+pub fn spawn_graspable(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    translation: Vec3,
+) -> Entity {
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Cube { size: 0.15 })),
+            material: materials.add(Color::rgb(0.2, 0.6, 0.3).into()),
+            transform: Transform::from_translation(translation),
+            ..Default::default()
+        })
+        .insert(Graspable)
+        .id()
+}
+
+pub fn spawn_light(commands: &mut Commands, position: Vec3, intensity: f32) -> Entity {
+    commands
+        .spawn_bundle(PointLightBundle {
+            point_light: PointLight {
+                intensity,
+                ..default()
+            },
+            transform: Transform::from_translation(position),
+            ..default()
+        })
+        .id()
+}
+
+// Runs before Update so that the respawned robot exists when the tool gets mounted
+#[allow(clippy::too_many_arguments)]
+pub fn load_scene(
+    mut commands: Commands,
+    mut events: EventReader<SceneCommand>,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut status: ResMut<SceneStatus>,
+    mut frames: ResMut<Frames>,
+    mut coordination: ResMut<Coordination>,
+    mut bookmarks: ResMut<CameraBookmarks>,
+    mut poses: ResMut<PoseLibrary>,
+    old: Query<Entity, SceneObject>,
+    mut camera: Query<(&mut Transform, &mut OrbitCamera)>,
+) {
+    for command in events.iter() {
+        let path = match command {
+            SceneCommand::Load(path) => path,
+            SceneCommand::Save(_) => continue,
+        };
+        let scene = match SceneFile::load(path) {
+            Ok(scene) => scene,
+            Err(err) => {
+                status.message = format!("Load failed: {}", err);
+                continue;
+            }
+        };
+
+        for entity in old.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        for (number, robot) in scene.robots.iter().enumerate() {
+            let name = if robot.name.is_empty() {
+                format!("Robot {}", number + 1)
+            } else {
+                robot.name.clone()
+            };
+            let entity = spawn_robot(
+                &mut commands,
+                &asset_server,
+                &mut materials,
+                &name,
+                robot.base.transform(),
+            );
+            let mut joint_state = JointState::default();
+            for (index, angle) in robot.joints.iter().enumerate() {
+                joint_state.set(index, angle.to_radians());
+            }
+            commands
+                .entity(entity)
+                .insert(joint_state)
+                .insert(robot.tool.clone())
+                .insert(Program::new(robot.program.clone()))
+                .insert(robot.dynamics.clone())
+                .insert(robot.payload)
+                .insert(ServoControl::new(robot.servo));
+        }
+        for obstacle in scene.obstacles.iter() {
+            spawn_obstacle(
+                &mut commands,
+                &mut meshes,
+                &mut materials,
+                obstacle.pose.transform(),
+                Obstacle {
+                    size: obstacle.size,
+                    color: obstacle.color,
+                },
+            );
+        }
+        for part in scene.parts.iter() {
+            spawn_graspable(&mut commands, &mut meshes, &mut materials, *part);
+        }
+        for light in scene.lights.iter() {
+            spawn_light(&mut commands, light.position, light.intensity);
+        }
+        for (mut transform, mut orbit) in camera.iter_mut() {
+            *transform = scene.camera.transform();
+            // An orbiting camera is placed from its orbit, not its transform
+            *orbit = OrbitCamera::from_transform(&transform, orbit.distance);
+        }
+        bookmarks.list = scene.bookmarks;
+        // Scenes without poses keep the library loaded from disk
+        if !scene.poses.is_empty() {
+            poses.poses = scene.poses;
+        }
+        *frames = scene.frames;
+        frames.validate();
+        *coordination = Coordination {
+            zones: scene.zones,
+            ..Default::default()
+        };
+        status.message = format!("Loaded {}", path);
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn save_scene(
+    mut events: EventReader<SceneCommand>,
+    mut status: ResMut<SceneStatus>,
+    frames: Res<Frames>,
+    coordination: Res<Coordination>,
+    bookmarks: Res<CameraBookmarks>,
+    poses: Res<PoseLibrary>,
+    robots: Query<(
+        &Robot,
+        &Transform,
+        &JointState,
+        &ToolSettings,
+        &Program,
+        &RobotDynamics,
+        &Payload,
+        &ServoControl,
+    )>,
+    obstacles: Query<(&Obstacle, &Transform)>,
+    parts: Query<&GlobalTransform, SavedPart>,
+    lights: Query<(&PointLight, &Transform)>,
+    camera: Query<&Transform, With<OrbitCamera>>,
+) {
+    for command in events.iter() {
+        let path = match command {
+            SceneCommand::Save(path) => path,
+            SceneCommand::Load(_) => continue,
+        };
+        let scene = SceneFile {
+            robots: robots
+                .iter()
+                .map(
+                    |(robot, base, joint_state, tool, program, dynamics, payload, servo)| {
+                        RobotEntry {
+                            name: robot.name.clone(),
+                            base: Pose::from(base),
+                            joints: joint_state.angles.map(f32::to_degrees),
+                            tool: tool.clone(),
+                            program: program.instructions.clone(),
+                            dynamics: dynamics.clone(),
+                            payload: *payload,
+                            servo: servo.tuning,
+                        }
+                    },
+                )
+                .collect(),
+            obstacles: obstacles
+                .iter()
+                .map(|(obstacle, transform)| ObstacleEntry {
+                    pose: Pose::from(transform),
+                    size: obstacle.size,
+                    color: obstacle.color,
+                })
+                .collect(),
+            parts: parts
+                .iter()
+                .map(|transform| transform.translation)
+                .collect(),
+            frames: frames.clone(),
+            lights: lights
+                .iter()
+                .map(|(light, transform)| LightEntry {
+                    position: transform.translation,
+                    intensity: light.intensity,
+                })
+                .collect(),
+            camera: camera
+                .iter()
+                .next()
+                .map_or(Pose::from(&Transform::identity()), Pose::from),
+            zones: coordination.zones.clone(),
+            bookmarks: bookmarks.list.clone(),
+            poses: poses.poses.clone(),
+        };
+        status.message = match scene.save(path) {
+            Ok(()) => format!("Saved {}", path),
+            Err(err) => format!("Save failed: {}", err),
+        };
+    }
+}
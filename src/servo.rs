@@ -0,0 +1,209 @@
+use std::collections::VecDeque;
+
+use bevy::{core::FixedTimesteps, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dynamics::{chain_links, forward_dynamics, inverse_dynamics, Payload, RobotDynamics, GRAVITY},
+    kinematics::{chain_axes, joint_frames, link_transforms, JointFrame, JOINT_COUNT},
+    robot::{Joint, JointState, JOINT_LIMITS},
+};
+
+// Label of the fixed timestep the servo loop runs in
+pub const SERVO_TIMESTEP: &str = "servo_timestep";
+
+// Integration steps per fixed timestep, keeps the stiff gains of the small wrist links stable
+const SUBSTEPS: usize = 10;
+const HISTORY_SECONDS: f64 = 10.0;
+
+// Seconds since startup, setpoint and actual angles
+pub type ServoSample = (f64, [f32; JOINT_COUNT], [f32; JOINT_COUNT]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ServoGains {
+    // Nm/rad, Nm/(rad s) and Nm s/rad
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+}
+
+// Controller settings of a robot, saved with it in the scene file
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ServoTuning {
+    pub gains: [ServoGains; JOINT_COUNT],
+    // Adds the gravity torque of the current pose to the controller output
+    pub feedforward: bool,
+}
+
+impl Default for ServoTuning {
+    fn default() -> Self {
+        // About 15 rad/s bandwidth and 0.6 damping for the default link masses
+        let gains = |kp, kd| ServoGains { kp, ki: 0.0, kd };
+        Self {
+            gains: [
+                gains(22500.0, 1800.0),
+                gains(30000.0, 2500.0),
+                gains(20000.0, 1500.0),
+                gains(700.0, 50.0),
+                gains(15.0, 1.2),
+            ],
+            feedforward: true,
+        }
+    }
+}
+
+// Simulated joint servos. When enabled the joint state is the setpoint and the arm
+// follows it through the controllers and the rigid body dynamics.
+#[derive(Component, Debug, Clone, Default)]
+pub struct ServoControl {
+    pub enabled: bool,
+    pub tuning: ServoTuning,
+    pub position: [f32; JOINT_COUNT],
+    pub velocity: [f32; JOINT_COUNT],
+    pub integral: [f32; JOINT_COUNT],
+    pub previous_setpoint: [f32; JOINT_COUNT],
+    pub history: VecDeque<ServoSample>,
+}
+
+impl ServoControl {
+    pub fn new(tuning: ServoTuning) -> Self {
+        Self {
+            tuning,
+            ..Default::default()
+        }
+    }
+
+    // Starts at rest on the setpoint
+    pub fn enable(&mut self, setpoint: &[f32; JOINT_COUNT]) {
+        self.enabled = true;
+        self.position = *setpoint;
+        self.previous_setpoint = *setpoint;
+        self.velocity = [0.0; JOINT_COUNT];
+        self.integral = [0.0; JOINT_COUNT];
+        self.history.clear();
+    }
+
+    // Angles the arm is actually at
+    pub fn angles(&self, state: &JointState) -> [f32; JOINT_COUNT] {
+        if self.enabled {
+            self.position
+        } else {
+            state.angles
+        }
+    }
+
+    pub fn following_error(&self, state: &JointState) -> [f32; JOINT_COUNT] {
+        let mut error = state.angles;
+        for (error, position) in error.iter_mut().zip(self.angles(state)) {
+            *error -= position;
+        }
+        error
+    }
+
+    // Advances the controllers and the arm by `dt` towards the setpoint
+    #[allow(clippy::too_many_arguments)]
+    pub fn step(
+        &mut self,
+        base: &GlobalTransform,
+        frames: &[JointFrame; JOINT_COUNT],
+        dynamics: &RobotDynamics,
+        payload: &Payload,
+        setpoint: &[f32; JOINT_COUNT],
+        setpoint_velocity: &[f32; JOINT_COUNT],
+        dt: f32,
+    ) {
+        let transforms = link_transforms(base, frames, &self.position);
+        let (origins, axes) = chain_axes(base, &transforms, frames);
+        let links = chain_links(dynamics, payload, &transforms);
+        let feedforward = if self.tuning.feedforward {
+            inverse_dynamics(
+                &origins,
+                &axes,
+                &links,
+                &[0.0; JOINT_COUNT],
+                &[0.0; JOINT_COUNT],
+                GRAVITY,
+            )
+        } else {
+            [0.0; JOINT_COUNT]
+        };
+
+        let mut torques = [0.0; JOINT_COUNT];
+        for index in 0..JOINT_COUNT {
+            let gains = self.tuning.gains[index];
+            let limit = dynamics.motor_limits[index];
+            let error = setpoint[index] - self.position[index];
+            // The integral alone may not ask for more than the motor can give
+            if gains.ki > 0.0 {
+                self.integral[index] =
+                    (self.integral[index] + error * dt).clamp(-limit / gains.ki, limit / gains.ki);
+            }
+            let torque = gains.kp * error
+                + gains.ki * self.integral[index]
+                + gains.kd * (setpoint_velocity[index] - self.velocity[index])
+                + feedforward[index];
+            torques[index] = torque.clamp(-limit, limit);
+        }
+
+        let acceleration =
+            forward_dynamics(&origins, &axes, &links, &self.velocity, &torques, GRAVITY);
+        for index in 0..JOINT_COUNT {
+            self.velocity[index] += acceleration[index] * dt;
+            self.position[index] += self.velocity[index] * dt;
+            // Hard stops at the joint limits
+            let (min, max) = JOINT_LIMITS[index];
+            let limited = self.position[index].clamp(min.to_radians(), max.to_radians());
+            if limited != self.position[index] {
+                self.position[index] = limited;
+                self.velocity[index] = 0.0;
+            }
+        }
+    }
+}
+
+pub fn run_servos(
+    time: Res<Time>,
+    timesteps: Res<FixedTimesteps>,
+    joints: Query<(&Joint, &Transform)>,
+    mut robots: Query<(
+        Entity,
+        &GlobalTransform,
+        &JointState,
+        &RobotDynamics,
+        &Payload,
+        &mut ServoControl,
+    )>,
+) {
+    let period = timesteps
+        .get(SERVO_TIMESTEP)
+        .map_or(1.0 / 60.0, |timestep| timestep.step()) as f32;
+    let now = time.seconds_since_startup();
+    for (robot, base, state, dynamics, payload, mut servo) in robots.iter_mut() {
+        if !servo.enabled {
+            continue;
+        }
+        let frames = joint_frames(robot, joints.iter());
+        let mut setpoint_velocity = state.angles;
+        for (velocity, previous) in setpoint_velocity.iter_mut().zip(servo.previous_setpoint) {
+            *velocity = (*velocity - previous) / period;
+        }
+        for _ in 0..SUBSTEPS {
+            servo.step(
+                base,
+                &frames,
+                dynamics,
+                payload,
+                &state.angles,
+                &setpoint_velocity,
+                period / SUBSTEPS as f32,
+            );
+        }
+        servo.previous_setpoint = state.angles;
+
+        let position = servo.position;
+        servo.history.push_back((now, state.angles, position));
+        while matches!(servo.history.front(), Some((time, ..)) if now - time > HISTORY_SECONDS) {
+            servo.history.pop_front();
+        }
+    }
+}
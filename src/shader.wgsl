@@ -6,6 +6,14 @@ struct InfiniteGrid {
     x_axis_col: vec3<f32>;
     z_axis_col: vec3<f32>;
     shadow_col: vec4<f32>;
+    minor_line_col: vec4<f32>;
+    major_line_col: vec4<f32>;
+    subdivisions: f32;
+    line_width: f32;
+    fade_distance: f32;
+    shadow_softness: f32;
+    box_count: u32;
+    boxes: array<vec4<f32>, 256>;
 };
 
 struct View {
@@ -56,22 +64,34 @@ fn vertex(vertex: Vertex) -> VertexOutput {
     return out;
 }
 
-fn grid(real_coords: vec3<f32>, plane_coords: vec2<f32>, scale: f32, shadow: f32, real_depth: f32) -> vec4<f32> {
-    let coord = plane_coords * scale; // use the scale variable to set the distance between the lines
+// Coverage of the lines at every `1 / scale` along both plane axes, widths in pixels
+fn line_alpha(coord: vec2<f32>, width: f32) -> f32 {
     let derivative = fwidth(coord);
     let grid = abs(fract(coord - 0.5) - 0.5) / derivative;
     let line = min(grid.x, grid.y);
+    return 1.0 - min(line / width, 1.0);
+}
+
+fn grid(real_coords: vec3<f32>, plane_coords: vec2<f32>, scale: f32, shadow: f32, real_depth: f32) -> vec4<f32> {
+    let coord = plane_coords * scale; // use the scale variable to set the distance between the lines
+    let derivative = fwidth(coord);
 
     let minimumz = min(derivative.y, 1.) / scale;
     let minimumx = min(derivative.x, 1.) / scale;
 
-    let base_alpha = 1.0 - min(line, 1.0);
-    let dist_fadeout = min(1., 1. - infinite_grid.scale * real_depth / 100.);
+    // Minor lines fade out before they are closer than a few pixels
+    let pixels_per_cell = 1.0 / max(derivative.x, derivative.y);
+    let minor_visibility = clamp((pixels_per_cell - 2.0) / 6.0, 0.0, 1.0);
+    let minor_alpha = line_alpha(coord, infinite_grid.line_width) * minor_visibility;
+    let major_alpha = line_alpha(coord / infinite_grid.subdivisions, infinite_grid.line_width);
+
+    var color = vec4<f32>(infinite_grid.minor_line_col.rgb, minor_alpha * infinite_grid.minor_line_col.a);
+    color = mix(color, infinite_grid.major_line_col, major_alpha);
+
+    let dist_fadeout = min(1., 1. - real_depth / infinite_grid.fade_distance);
     let dot_fadeout = abs(dot(infinite_grid.normal, normalize(view.world_position - real_coords)));
     let alpha_fadeout = mix(dist_fadeout, 1., dot_fadeout);
-    let true_alpha = base_alpha * alpha_fadeout * step(0.01, abs(dot(infinite_grid.normal, view.world_position - real_coords)));
-
-    var color = vec4<f32>(vec3<f32>(0.2), true_alpha);
+    color.a = color.a * alpha_fadeout * step(0.01, abs(dot(infinite_grid.normal, view.world_position - real_coords)));
 
     color = mix(color, infinite_grid.shadow_col, 1. - shadow);
 
@@ -84,6 +104,45 @@ fn grid(real_coords: vec3<f32>, plane_coords: vec2<f32>, scale: f32, shadow: f32
     return color;
 }
 
+// How much the boxes above the point darken it: full under a box, weaker the higher the box is,
+// plus a contact shadow around boxes that touch the plane
+fn shadow(point: vec3<f32>, normal: vec3<f32>) -> f32 {
+    var occlusion = 0.0;
+    for (var i = 0u; i < infinite_grid.box_count; i = i + 1u) {
+        let offset = point - infinite_grid.boxes[i * 4u + 3u].xyz;
+        var enter = 0.0;
+        var exit = 1e9;
+        var outside = vec3<f32>(0.0);
+        for (var axis = 0u; axis < 3u; axis = axis + 1u) {
+            let edge = infinite_grid.boxes[i * 4u + axis].xyz;
+            let length_squared = max(dot(edge, edge), 1e-8);
+            // Position and ray direction in box units, the faces are at -1 and 1
+            let q = dot(offset, edge) / length_squared;
+            let k = dot(normal, edge) / length_squared;
+            let distance = max(abs(q) - 1.0, 0.0) * sqrt(length_squared);
+            outside = select(outside, vec3<f32>(distance, outside.y, outside.z), axis == 0u);
+            outside = select(outside, vec3<f32>(outside.x, distance, outside.z), axis == 1u);
+            outside = select(outside, vec3<f32>(outside.x, outside.y, distance), axis == 2u);
+            if (abs(k) < 1e-6) {
+                if (abs(q) > 1.0) {
+                    enter = 1e9;
+                }
+            } else {
+                let t0 = (-1.0 - q) / k;
+                let t1 = (1.0 - q) / k;
+                enter = max(enter, min(t0, t1));
+                exit = min(exit, max(t0, t1));
+            }
+        }
+        if (enter <= exit) {
+            occlusion = max(occlusion, 1.0 / (1.0 + enter));
+        }
+        let contact = 1.0 - clamp(length(outside) / infinite_grid.shadow_softness, 0.0, 1.0);
+        occlusion = max(occlusion, contact);
+    }
+    return 1.0 - occlusion;
+}
+
 struct FragmentOutput {
     [[location(0)]] color: vec4<f32>;
     [[builtin(frag_depth)]] depth: f32;
@@ -115,7 +174,7 @@ fn fragment(in: VertexOutput) -> FragmentOutput {
 
     out.depth = clip_depth;
 
-    out.color = grid(frag_pos_3d, plane_coords, infinite_grid.scale, 1., real_depth);
+    out.color = grid(frag_pos_3d, plane_coords, infinite_grid.scale, shadow(frag_pos_3d, plane_normal), real_depth);
 
     return out;
 }
@@ -1,105 +1,1574 @@
 use crate::{
-    elbow::ElbowRotate, lower_arm::LowerArmRotate, shoulder::ShoulderRotate,
-    upper_arm::UpperArmRotate, wrist::WristRotate,
+    camera::{
+        CameraBookmarks, CameraCommand, CameraMode, CameraSettings, FollowTarget, ViewPreset,
+    },
+    cartesian::{CartesianJog, CartesianMove, JogFrame},
+    collision::RobotCollisions,
+    conveyor::{Conveyor, PartSpawner, PresenceSensor},
+    dynamics::{JointTorques, Payload, PayloadLoad, RobotDynamics},
+    frames::{Frame, Frames, FRAMES_FILE},
+    ghost::{GhostPreview, GhostTarget},
+    gizmo::GizmoSettings,
+    grids::{grid_placements, GridCommand, GridPlacement, ReferenceGrid},
+    jog::{JogMode, JogSettings, SpeedOverride},
+    kinematics::{Manipulability, JOINT_COUNT},
+    manipulator::{Manipulator, ManipulatorMode},
+    pendant::Pendant,
+    picking::{Picked, Selection},
+    poses::{PoseLibrary, PoseMove, POSES_FILE},
+    program::{Coordination, Instruction, Program, Zone},
+    report::{CycleRecorder, DEFAULT_REPORT_FILE},
+    robot::{ActiveRobot, JointState, Robot, RobotCommand, JOINT_LIMITS, JOINT_NAMES},
+    scene::{scene_argument, Obstacle, SceneCommand, SceneStatus, DEFAULT_SCENE_FILE},
+    servo::{ServoControl, ServoSample},
+    tool::{Graspable, GripperCommand, Tool, ToolKind, ToolSettings},
+    trail::{ClearTrails, TrailSettings},
+    viewport::{ViewportSettings, ViewportView},
+    GridUnit, InfiniteGrid,
 };
-use bevy::prelude::*;
-use bevy_egui::{egui, EguiContext};
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_egui::{
+    egui::{
+        self,
+        plot::{HLine, Line, Plot, Value, Values},
+    },
+    EguiContext,
+};
+use std::{path::Path, time::Duration};
 
-#[derive(Default)]
 pub struct UiState {
-    value1: f32,
-    prev_value1: f32,
-    value2: f32,
-    prev_value2: f32,
-    value3: f32,
-    prev_value3: f32,
-    value4: f32,
-    prev_value4: f32,
-    value5: f32,
-    prev_value5: f32,
+    // Robot whose tool settings are being edited
+    robot: Option<Entity>,
+    tool: ToolSettings,
+    move_target: Vec3,
+    // Show a ghost of the Cartesian move target and of the selected program waypoint
+    preview_move: bool,
+    selected_instruction: Option<usize>,
+    frames_status: String,
+    scene_path: String,
+    // Inputs for new program instructions
+    program_speed: f32,
+    wait_seconds: f32,
+    signal_name: String,
+    report_path: String,
+    report_status: String,
+    bookmark_name: String,
+    // Name new poses are saved under and program pose moves go to
+    pose_name: String,
+    poses_status: String,
 }
 
-pub fn ui_example(
-    mut query1: Query<(&ShoulderRotate, &mut Transform)>,
-    mut query2: Query<(&LowerArmRotate, &mut Transform), Without<ShoulderRotate>>,
-    mut query3: Query<
-        (&ElbowRotate, &mut Transform),
-        (Without<LowerArmRotate>, Without<ShoulderRotate>),
-    >,
-    mut query4: Query<
-        (&UpperArmRotate, &mut Transform),
+impl Default for UiState {
+    fn default() -> Self {
+        Self {
+            robot: None,
+            tool: Default::default(),
+            move_target: Vec3::ZERO,
+            preview_move: false,
+            selected_instruction: None,
+            frames_status: String::new(),
+            scene_path: scene_argument().unwrap_or_else(|| DEFAULT_SCENE_FILE.to_string()),
+            program_speed: 60.0,
+            wait_seconds: 1.0,
+            signal_name: "ready".to_string(),
+            report_path: DEFAULT_REPORT_FILE.to_string(),
+            report_status: String::new(),
+            bookmark_name: "View 1".to_string(),
+            pose_name: "HOME".to_string(),
+            poses_status: String::new(),
+        }
+    }
+}
+
+// A missing file keeps the built-in poses, a broken one is reported before Save replaces it
+pub fn load_poses(mut library: ResMut<PoseLibrary>, mut ui_state: ResMut<UiState>) {
+    if !Path::new(POSES_FILE).exists() {
+        return;
+    }
+    match PoseLibrary::load(POSES_FILE) {
+        Ok(loaded) => *library = loaded,
+        Err(err) => ui_state.poses_status = format!("Load failed: {}", err),
+    }
+}
+
+#[derive(SystemParam)]
+pub struct RobotControls<'w, 's> {
+    active: ResMut<'w, ActiveRobot>,
+    robots: Query<
+        'w,
+        's,
         (
-            Without<LowerArmRotate>,
-            Without<ShoulderRotate>,
-            Without<ElbowRotate>,
+            Entity,
+            &'static Robot,
+            &'static mut Transform,
+            &'static mut JointState,
+            &'static mut ToolSettings,
+            &'static Manipulability,
         ),
     >,
-    mut query5: Query<
-        (&WristRotate, &mut Transform),
+    tools: Query<'w, 's, &'static Tool>,
+    dynamics: Query<'w, 's, (&'static RobotDynamics, &'static JointTorques)>,
+    payloads: Query<'w, 's, (&'static mut Payload, &'static PayloadLoad)>,
+    servos: Query<'w, 's, &'static mut ServoControl>,
+    commands: EventWriter<'w, 's, RobotCommand>,
+    gripper: EventWriter<'w, 's, GripperCommand>,
+    collisions: Res<'w, RobotCollisions>,
+}
+
+#[derive(SystemParam)]
+pub struct ProgramControls<'w, 's> {
+    programs: Query<'w, 's, &'static mut Program>,
+    recorders: Query<'w, 's, &'static CycleRecorder>,
+    coordination: ResMut<'w, Coordination>,
+    pendant: ResMut<'w, Pendant>,
+    poses: ResMut<'w, PoseLibrary>,
+    pose_move: ResMut<'w, PoseMove>,
+}
+
+#[derive(SystemParam)]
+pub struct DisplayControls<'w, 's> {
+    trail: ResMut<'w, TrailSettings>,
+    clear_trails: EventWriter<'w, 's, ClearTrails>,
+    gizmos: ResMut<'w, GizmoSettings>,
+    grids: Query<
+        'w,
+        's,
         (
-            Without<LowerArmRotate>,
-            Without<ShoulderRotate>,
-            Without<ElbowRotate>,
-            Without<UpperArmRotate>,
+            Entity,
+            &'static mut ReferenceGrid,
+            &'static mut InfiniteGrid,
+            &'static mut Visibility,
         ),
     >,
+    grid_commands: EventWriter<'w, 's, GridCommand>,
+    camera: ResMut<'w, CameraSettings>,
+    camera_commands: EventWriter<'w, 's, CameraCommand>,
+    bookmarks: ResMut<'w, CameraBookmarks>,
+    viewports: ResMut<'w, ViewportSettings>,
+}
+
+// Obstacles and parts not held by a tool, robot bases are placed in the robot section
+type LooseObjects = (
+    Or<(With<Obstacle>, With<Graspable>)>,
+    Without<Parent>,
+    Without<Robot>,
+);
+
+#[derive(SystemParam)]
+pub struct SelectionControls<'w, 's> {
+    selection: ResMut<'w, Selection>,
+    obstacles: Query<'w, 's, &'static mut Obstacle>,
+    parts: Query<'w, 's, (), With<Graspable>>,
+    transforms: Query<'w, 's, &'static mut Transform, LooseObjects>,
+    manipulator: ResMut<'w, Manipulator>,
+    commands: Commands<'w, 's>,
+}
+
+#[derive(SystemParam)]
+pub struct ConveyorControls<'w, 's> {
+    conveyors: Query<'w, 's, &'static mut Conveyor>,
+    spawners: Query<'w, 's, &'static mut PartSpawner>,
+    sensors: Query<'w, 's, &'static PresenceSensor>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn ui_example(
     mut egui_ctx: ResMut<EguiContext>,
     mut ui_state: ResMut<UiState>,
+    mut robot_controls: RobotControls,
+    mut frames: ResMut<Frames>,
+    mut jog: ResMut<CartesianJog>,
+    mut jog_settings: ResMut<JogSettings>,
+    mut speed_override: ResMut<SpeedOverride>,
+    mut cartesian_move: ResMut<CartesianMove>,
+    mut conveyor_controls: ConveyorControls,
+    mut program_controls: ProgramControls,
+    mut scene_commands: EventWriter<SceneCommand>,
+    scene_status: Res<SceneStatus>,
+    mut display_controls: DisplayControls,
+    mut selection_controls: SelectionControls,
+    mut ghost: ResMut<GhostPreview>,
 ) {
-    let (_, mut transform1) = query1.single_mut();
-    let (_, mut transform2) = query2.single_mut();
-    let (_, mut transform3) = query3.single_mut();
-    let (_, mut transform4) = query4.single_mut();
-    let (_, mut transform5) = query5.single_mut();
+    jog.linear = Vec3::ZERO;
+    jog.angular = Vec3::ZERO;
+    let mut ghost_target = None;
+
+    let controls = &mut robot_controls;
+    let names: Vec<(Entity, String)> = controls
+        .robots
+        .iter()
+        .map(|(entity, robot, ..)| (entity, robot.name.clone()))
+        .collect();
+    let name_of = |entity: Entity| robot_name(&names, entity);
+    let mut active_robot = controls
+        .active
+        .entity
+        .and_then(|entity| controls.robots.get_mut(entity).ok());
 
     egui::SidePanel::left("side_panel")
         .default_width(300.0)
         .show(egui_ctx.ctx_mut(), |ui| {
-            ui.heading("Rotating");
+            for (a, b, depth) in controls.collisions.pairs.iter() {
+                ui.colored_label(
+                    egui::Color32::from_rgb(255, 80, 80),
+                    format!(
+                        "Collision: {} and {} ({:.0} mm)",
+                        name_of(*a),
+                        name_of(*b),
+                        depth * 1000.0
+                    ),
+                );
+            }
+            if program_controls.coordination.deadlock {
+                ui.colored_label(
+                    egui::Color32::from_rgb(255, 80, 80),
+                    "Deadlock: all running programs are waiting",
+                );
+            }
+            if let Some((robot, .., manipulability)) = &active_robot {
+                if let Some(singularity) = manipulability.singularity {
+                    ui.colored_label(
+                        egui::Color32::from_rgb(255, 80, 80),
+                        format!("Warning: close to {} singularity", singularity.name()),
+                    );
+                }
+                if let Ok((dynamics, torques)) = controls.dynamics.get(*robot) {
+                    for index in torques.overloaded(&dynamics.motor_limits) {
+                        ui.colored_label(
+                            egui::Color32::from_rgb(255, 80, 80),
+                            format!("Warning: {} torque over motor limit", JOINT_NAMES[index]),
+                        );
+                    }
+                    if let Ok((payload, load)) = controls.payloads.get(*robot) {
+                        for violation in dynamics.rated_load.violations(payload, load) {
+                            ui.colored_label(
+                                egui::Color32::from_rgb(255, 80, 80),
+                                format!("Warning: {}", violation),
+                            );
+                        }
+                    }
+                }
+            }
 
+            ui.heading("Scene");
             ui.horizontal(|ui| {
-                ui.label("Shoulder: ");
+                ui.text_edit_singleline(&mut ui_state.scene_path);
+                if ui.button("Load").clicked() {
+                    scene_commands.send(SceneCommand::Load(ui_state.scene_path.clone()));
+                }
+                if ui.button("Save").clicked() {
+                    scene_commands.send(SceneCommand::Save(ui_state.scene_path.clone()));
+                }
             });
-            ui_state.prev_value1 = ui_state.value1;
-            ui.add(egui::Slider::new(&mut ui_state.value1, -180.0..=180.0));
-            transform1.rotate(Quat::from_rotation_y(
-                (ui_state.value1 - ui_state.prev_value1).to_radians(),
-            ));
+            if !scene_status.message.is_empty() {
+                ui.label(&scene_status.message);
+            }
+
+            ui.separator();
+            ui.heading("Selection");
+            let selection = &mut *selection_controls.selection;
+            match selection.picked {
+                None => {
+                    ui.label("Click a link or an object in the view");
+                }
+                Some(Picked::Link { robot, index, .. }) => {
+                    ui.label(format!(
+                        "{} {}, drag to turn it",
+                        name_of(robot),
+                        JOINT_NAMES[index]
+                    ));
+                }
+                Some(Picked::Frame(index)) => {
+                    let name = frames
+                        .user
+                        .get(index)
+                        .map_or("removed", |frame| &frame.name);
+                    ui.label(format!(
+                        "User frame {}, edit it in the Frames section",
+                        name
+                    ));
+                }
+                Some(Picked::Object(entity)) => {
+                    if let Ok(mut transform) = selection_controls.transforms.get_mut(entity) {
+                        let mut edited = *transform;
+                        transform_edit(ui, &mut edited);
+                        if edited != *transform {
+                            *transform = edited;
+                        }
+                    }
+                    let mut removed = false;
+                    if names.iter().any(|(robot, _)| *robot == entity) {
+                        ui.horizontal(|ui| {
+                            ui.label(format!("Robot base of {}", name_of(entity)));
+                            if ui.button("Remove").clicked() {
+                                controls.commands.send(RobotCommand::Remove(entity));
+                                removed = true;
+                            }
+                        });
+                    } else if let Ok(mut obstacle) = selection_controls.obstacles.get_mut(entity) {
+                        let mut size = obstacle.size.to_array();
+                        let mut rgba = obstacle.color.as_rgba_f32();
+                        ui.horizontal(|ui| {
+                            ui.label("Obstacle size: ");
+                            for value in size.iter_mut() {
+                                ui.add(
+                                    egui::DragValue::new(value)
+                                        .speed(0.01)
+                                        .clamp_range(0.01..=10.0),
+                                );
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.color_edit_button_rgba_unmultiplied(&mut rgba);
+                            if ui.button("Delete").clicked() {
+                                removed = true;
+                            }
+                        });
+                        let color = Color::rgba(rgba[0], rgba[1], rgba[2], rgba[3]);
+                        let size = Vec3::from(size);
+                        if size != obstacle.size || color != obstacle.color {
+                            obstacle.size = size;
+                            obstacle.color = color;
+                        }
+                    } else if selection_controls.parts.contains(entity) {
+                        ui.horizontal(|ui| {
+                            ui.label("Part");
+                            if ui.button("Delete").clicked() {
+                                removed = true;
+                            }
+                        });
+                    }
+                    if removed {
+                        selection_controls
+                            .commands
+                            .entity(entity)
+                            .despawn_recursive();
+                        selection.picked = None;
+                    }
+                }
+            }
+            if matches!(
+                selection.picked,
+                Some(Picked::Object(_)) | Some(Picked::Frame(_))
+            ) {
+                let manipulator = &mut *selection_controls.manipulator;
+                ui.horizontal(|ui| {
+                    ui.label("Handle: ");
+                    ui.radio_value(&mut manipulator.mode, ManipulatorMode::Translate, "Move");
+                    ui.radio_value(&mut manipulator.mode, ManipulatorMode::Rotate, "Rotate");
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut manipulator.snap, "Snap");
+                    ui.add(
+                        egui::DragValue::new(&mut manipulator.translate_step)
+                            .speed(0.01)
+                            .clamp_range(0.001..=10.0)
+                            .suffix(" m"),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut manipulator.angle_step)
+                            .speed(1.0)
+                            .clamp_range(1.0..=90.0)
+                            .suffix("°"),
+                    );
+                    // Grid increments of the ground grid
+                    let ground = display_controls
+                        .grids
+                        .iter()
+                        .find(|(_, grid, ..)| grid.placement == GridPlacement::Ground);
+                    if let Some((.., grid, _)) = ground {
+                        if ui.button("Grid step").clicked() {
+                            manipulator.translate_step = grid.cell_size;
+                        }
+                    }
+                });
+            }
 
+            ui.separator();
+            ui.heading("Robots");
             ui.horizontal(|ui| {
-                ui.label("Lower Arm: ");
+                let selected = controls.active.entity.map_or("none", name_of);
+                let mut choice = controls.active.entity;
+                egui::ComboBox::from_label("Active")
+                    .selected_text(selected)
+                    .show_ui(ui, |ui| {
+                        for (entity, name) in names.iter() {
+                            ui.selectable_value(&mut choice, Some(*entity), name.as_str());
+                        }
+                    });
+                if choice != controls.active.entity {
+                    controls.active.entity = choice;
+                }
+                if ui.button("Add").clicked() {
+                    controls.commands.send(RobotCommand::Add);
+                }
+                if let Some(entity) = controls.active.entity {
+                    if ui.button("Remove").clicked() {
+                        controls.commands.send(RobotCommand::Remove(entity));
+                    }
+                }
             });
-            ui_state.prev_value2 = ui_state.value2;
-            ui.add(egui::Slider::new(&mut ui_state.value2, -40.0..=90.0));
-            transform2.rotate(Quat::from_rotation_x(
-                (ui_state.value2 - ui_state.prev_value2).to_radians(),
-            ));
+            ui.checkbox(&mut program_controls.pendant.open, "Teach pendant");
 
-            ui.horizontal(|ui| {
-                ui.label("Elbow: ");
+            if let Some((robot, _, base, joint_state, tool_settings, manipulability)) =
+                &mut active_robot
+            {
+                let robot = *robot;
+                if ui_state.robot != Some(robot) {
+                    ui_state.robot = Some(robot);
+                    ui_state.tool = tool_settings.clone();
+                }
+
+                let mut translation = base.translation;
+                let (mut yaw, _, _) = base.rotation.to_euler(EulerRot::YXZ);
+                yaw = yaw.to_degrees();
+                let mut changed = false;
+                ui.horizontal(|ui| {
+                    ui.label("Base XYZ: ");
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut translation.x).speed(0.01))
+                        .changed();
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut translation.y).speed(0.01))
+                        .changed();
+                    changed |= ui
+                        .add(egui::DragValue::new(&mut translation.z).speed(0.01))
+                        .changed();
+                    ui.label("Yaw: ");
+                    changed |= ui.add(egui::DragValue::new(&mut yaw).speed(1.0)).changed();
+                });
+                if changed {
+                    base.translation = translation;
+                    base.rotation = Quat::from_rotation_y(yaw.to_radians());
+                }
+
+                ui.separator();
+                ui.heading("Rotating");
+
+                for (index, name) in JOINT_NAMES.iter().enumerate() {
+                    let picked = matches!(
+                        selection_controls.selection.picked,
+                        Some(Picked::Link { robot: picked, index: joint, .. })
+                            if picked == robot && joint == index
+                    );
+                    ui.horizontal(|ui| {
+                        if picked {
+                            ui.colored_label(
+                                egui::Color32::from_rgb(255, 200, 60),
+                                format!("{}: ", name),
+                            );
+                        } else {
+                            ui.label(format!("{}: ", name));
+                        }
+                    });
+                    let (min, max) = JOINT_LIMITS[index];
+                    let mut value = joint_state.angles[index].to_degrees();
+                    let slider = ui.add(egui::Slider::new(&mut value, min..=max));
+                    if slider.changed() {
+                        joint_state.set(index, value.to_radians());
+                    }
+                    // Arrow keys then jog the picked joint, focusing the slider would block them
+                    if picked && selection_controls.selection.focus_slider {
+                        jog_settings.set_mode(JogMode::Joint);
+                        jog_settings.axis = index;
+                        slider.scroll_to_me(Some(egui::Align::Center));
+                        selection_controls.selection.focus_slider = false;
+                    }
+                }
+
+                ui.separator();
+                egui::CollapsingHeader::new("Poses")
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        poses_section(
+                            ui,
+                            &mut ui_state,
+                            &mut program_controls.poses,
+                            &mut program_controls.pose_move,
+                            &mut cartesian_move,
+                            joint_state,
+                        );
+                    });
+
+                ui.separator();
+                ui.heading("Tool");
+                egui::ComboBox::from_label("Type")
+                    .selected_text(ui_state.tool.kind.name())
+                    .show_ui(ui, |ui| {
+                        for kind in ToolKind::ALL {
+                            ui.selectable_value(&mut ui_state.tool.kind, kind, kind.name());
+                        }
+                    });
+                if ui_state.tool.kind == ToolKind::Custom {
+                    ui.horizontal(|ui| {
+                        ui.label("Mesh: ");
+                        ui.text_edit_singleline(&mut ui_state.tool.custom_mesh);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("TCP: ");
+                        ui.add(egui::DragValue::new(&mut ui_state.tool.custom_tcp.x).speed(0.01));
+                        ui.add(egui::DragValue::new(&mut ui_state.tool.custom_tcp.y).speed(0.01));
+                        ui.add(egui::DragValue::new(&mut ui_state.tool.custom_tcp.z).speed(0.01));
+                    });
+                }
+                if ui.button("Mount").clicked() && **tool_settings != ui_state.tool {
+                    **tool_settings = ui_state.tool.clone();
+                }
+                if let Some(tool) = controls.tools.iter().find(|tool| tool.robot == robot) {
+                    ui.horizontal(|ui| {
+                        if ui.button("Close").clicked() {
+                            controls.gripper.send(GripperCommand::Close(robot));
+                        }
+                        if ui.button("Open").clicked() {
+                            controls.gripper.send(GripperCommand::Open(robot));
+                        }
+                        ui.label(match (tool.closed, tool.grasped) {
+                            (_, Some(_)) => "holding part",
+                            (true, None) => "closed",
+                            (false, None) => "open",
+                        });
+                    });
+                }
+
+                ui.separator();
+                egui::CollapsingHeader::new("Frames")
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        let frames = &mut *frames;
+                        frame_list(ui, "Tool frame", &mut frames.tool, &mut frames.active_tool);
+                        frame_list(ui, "User frame", &mut frames.user, &mut frames.active_user);
+                        ui.horizontal(|ui| {
+                            if frames.active_user > 0 && ui.button("User frame at TCP").clicked() {
+                                if let Some(frame) = frames.user.get_mut(frames.active_user) {
+                                    frame.set_transform(&manipulability.tcp);
+                                }
+                            }
+                            if frames.active_user > 0 && ui.button("Move in view").clicked() {
+                                selection_controls.selection.picked =
+                                    Some(Picked::Frame(frames.active_user));
+                            }
+                            if ui.button("Save").clicked() {
+                                ui_state.frames_status = match frames.save(FRAMES_FILE) {
+                                    Ok(()) => format!("Saved to {}", FRAMES_FILE),
+                                    Err(err) => format!("Save failed: {}", err),
+                                };
+                            }
+                        });
+                        if !ui_state.frames_status.is_empty() {
+                            ui.label(&ui_state.frames_status);
+                        }
+
+                        let tcp = frames.to_user(&manipulability.tcp);
+                        let (rx, ry, rz) = tcp.rotation.to_euler(EulerRot::XYZ);
+                        ui.label(format!(
+                            "TCP in {}: X {:.3} Y {:.3} Z {:.3}",
+                            frames
+                                .user
+                                .get(frames.active_user)
+                                .map_or("?", |frame| frame.name.as_str()),
+                            tcp.translation.x,
+                            tcp.translation.y,
+                            tcp.translation.z
+                        ));
+                        ui.label(format!(
+                            "Rx {:.1} Ry {:.1} Rz {:.1}",
+                            rx.to_degrees(),
+                            ry.to_degrees(),
+                            rz.to_degrees()
+                        ));
+                    });
+
+                ui.separator();
+                egui::CollapsingHeader::new("Keyboard and gamepad jog")
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label("Mode: ");
+                            for mode in [JogMode::Joint, JogMode::Cartesian] {
+                                if ui.radio(jog_settings.mode == mode, mode.name()).clicked() {
+                                    jog_settings.set_mode(mode);
+                                }
+                            }
+                        });
+                        let mode = jog_settings.mode;
+                        ui.horizontal(|ui| {
+                            egui::ComboBox::from_label("Axis")
+                                .selected_text(mode.axis_name(jog_settings.axis))
+                                .show_ui(ui, |ui| {
+                                    for axis in 0..mode.axis_count() {
+                                        ui.selectable_value(
+                                            &mut jog_settings.axis,
+                                            axis,
+                                            mode.axis_name(axis),
+                                        );
+                                    }
+                                });
+                            if mode == JogMode::Cartesian {
+                                ui.label(format!("in {}", jog.frame.name()));
+                            }
+                        });
+                        ui.add(
+                            egui::Slider::new(&mut speed_override.percent, 1.0..=100.0).text("% override"),
+                        );
+                        if jog_settings.input != 0.0 {
+                            ui.label(format!(
+                                "Jogging {} {}{:.0} %",
+                                mode.axis_name(jog_settings.axis),
+                                if jog_settings.input > 0.0 { "+" } else { "-" },
+                                jog_settings.input.abs() * speed_override.percent
+                            ));
+                        }
+                        ui.label(format!(
+                            "Keys: 1-{} axis, left/right jog, Tab mode, F frame, PgUp/PgDn speed",
+                            mode.axis_count()
+                        ));
+                        ui.label("Gamepad: left stick jog, D-pad axis, North mode, West frame, shoulders speed");
+                    });
+
+                ui.separator();
+                egui::CollapsingHeader::new("Cartesian")
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        egui::ComboBox::from_label("Jog frame")
+                            .selected_text(jog.frame.name())
+                            .show_ui(ui, |ui| {
+                                for frame in JogFrame::ALL {
+                                    ui.selectable_value(&mut jog.frame, frame, frame.name());
+                                }
+                            });
+                        ui.add(egui::Slider::new(&mut jog.speed, 0.01..=1.0).text("m/s"));
+                        ui.add(egui::Slider::new(&mut jog.angular_speed, 1.0..=90.0).text("deg/s"));
+                        for (axis, direction) in [("X", Vec3::X), ("Y", Vec3::Y), ("Z", Vec3::Z)] {
+                            ui.horizontal(|ui| {
+                                if ui.button(format!("-{}", axis)).is_pointer_button_down_on() {
+                                    jog.linear = -direction;
+                                }
+                                if ui.button(format!("+{}", axis)).is_pointer_button_down_on() {
+                                    jog.linear = direction;
+                                }
+                                if ui.button(format!("-R{}", axis)).is_pointer_button_down_on() {
+                                    jog.angular = -direction;
+                                }
+                                if ui.button(format!("+R{}", axis)).is_pointer_button_down_on() {
+                                    jog.angular = direction;
+                                }
+                            });
+                        }
+
+                        ui.label("Move TCP to (user frame):");
+                        ui.horizontal(|ui| {
+                            ui.add(egui::DragValue::new(&mut ui_state.move_target.x).speed(0.01));
+                            ui.add(egui::DragValue::new(&mut ui_state.move_target.y).speed(0.01));
+                            ui.add(egui::DragValue::new(&mut ui_state.move_target.z).speed(0.01));
+                            if ui.button("Go").clicked() {
+                                cartesian_move.target = Some(ui_state.move_target);
+                                cartesian_move.reached = false;
+                            }
+                            if ui.button("Stop").clicked() {
+                                cartesian_move.target = None;
+                            }
+                        });
+                        ui.checkbox(&mut ui_state.preview_move, "Preview target");
+                        if ui_state.preview_move && cartesian_move.target.is_none() {
+                            ghost_target = Some(GhostTarget::Linear(ui_state.move_target));
+                        }
+                    });
+
+                ui.separator();
+                egui::CollapsingHeader::new("Program").show(ui, |ui| {
+                    if let Ok(mut program) = program_controls.programs.get_mut(robot) {
+                        program_section(
+                            ui,
+                            &mut ui_state,
+                            &mut program,
+                            &program_controls.poses,
+                            joint_state,
+                            &mut ghost_target,
+                        );
+                    }
+                });
+
+                egui::CollapsingHeader::new("Cycle report").show(ui, |ui| {
+                    let recorder = match program_controls.recorders.get(robot) {
+                        Ok(recorder) => recorder,
+                        Err(_) => return,
+                    };
+                    if let Some(report) = &recorder.recording {
+                        ui.label(format!("Recording: {:.1} s", report.cycle_time));
+                    }
+                    let report = match &recorder.last {
+                        Some(report) => report,
+                        None => {
+                            ui.label("Run the program or a linear move to get a report");
+                            return;
+                        }
+                    };
+                    let status = if report.complete { "" } else { ", stopped" };
+                    ui.label(format!("{}{}", report.source.name(), status));
+                    ui.label(format!("Cycle time: {:.2} s", report.cycle_time));
+                    ui.label(format!("Idle time: {:.2} s", report.idle_time));
+                    ui.label(format!(
+                        "Energy: {:.1} J ({:.1} J absorbed braking)",
+                        report.energy, report.regenerated
+                    ));
+                    egui::Grid::new("cycle_peaks").show(ui, |ui| {
+                        ui.label("");
+                        ui.label("deg/s");
+                        ui.label("deg/s²");
+                        ui.end_row();
+                        for (index, name) in JOINT_NAMES.iter().enumerate() {
+                            ui.label(*name);
+                            ui.label(format!("{:.1}", report.peak_velocity[index]));
+                            ui.label(format!("{:.1}", report.peak_acceleration[index]));
+                            ui.end_row();
+                        }
+                    });
+                    for segment in report.segments.iter() {
+                        let idle = if segment.idle { " (idle)" } else { "" };
+                        ui.label(format!(
+                            "{}. {}: {:.2} s{}",
+                            segment.index + 1,
+                            segment.instruction,
+                            segment.duration,
+                            idle
+                        ));
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label("File (.json or .md): ");
+                        ui.text_edit_singleline(&mut ui_state.report_path);
+                    });
+                    if ui.button("Export").clicked() {
+                        ui_state.report_status = match report.save(&ui_state.report_path) {
+                            Ok(()) => format!("Exported {}", ui_state.report_path),
+                            Err(err) => format!("Export failed: {}", err),
+                        };
+                    }
+                    if !ui_state.report_status.is_empty() {
+                        ui.label(&ui_state.report_status);
+                    }
+                });
+
+                ui.separator();
+                ui.heading("Manipulability");
+                ui.label(format!("Yoshikawa index: {:.4}", manipulability.yoshikawa));
+                ui.label(format!("Condition number: {:.1}", manipulability.condition));
+
+                ui.separator();
+                egui::CollapsingHeader::new("Payload").show(ui, |ui| {
+                    let (mut payload, load) = match controls.payloads.get_mut(robot) {
+                        Ok(robot) => robot,
+                        Err(_) => return,
+                    };
+                    let mut edited = *payload;
+                    ui.horizontal(|ui| {
+                        ui.label("Mass: ");
+                        ui.add(
+                            egui::DragValue::new(&mut edited.mass)
+                                .speed(0.1)
+                                .clamp_range(0.0..=100.0)
+                                .suffix(" kg"),
+                        );
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("CoG: ");
+                        ui.add(egui::DragValue::new(&mut edited.center_of_mass.x).speed(0.01));
+                        ui.add(egui::DragValue::new(&mut edited.center_of_mass.y).speed(0.01));
+                        ui.add(egui::DragValue::new(&mut edited.center_of_mass.z).speed(0.01));
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Inertia: ");
+                        ui.add(egui::DragValue::new(&mut edited.inertia.x).speed(0.01));
+                        ui.add(egui::DragValue::new(&mut edited.inertia.y).speed(0.01));
+                        ui.add(egui::DragValue::new(&mut edited.inertia.z).speed(0.01));
+                    });
+                    if edited != *payload {
+                        *payload = edited;
+                    }
+                    ui.label(format!(
+                        "Wrist moment {:.1} Nm, inertia {:.2} kg m²",
+                        load.moment, load.inertia
+                    ));
+                    ui.label(format!("TCP deflection {:.2} mm", load.deflection * 1000.0));
+                });
+
+                ui.separator();
+                egui::CollapsingHeader::new("Servo").show(ui, |ui| {
+                    let mut servo = match controls.servos.get_mut(robot) {
+                        Ok(servo) => servo,
+                        Err(_) => return,
+                    };
+                    let mut enabled = servo.enabled;
+                    if ui.checkbox(&mut enabled, "Dynamic mode").changed() {
+                        if enabled {
+                            servo.enable(&joint_state.angles);
+                        } else {
+                            servo.enabled = false;
+                        }
+                    }
+                    let mut tuning = servo.tuning;
+                    ui.checkbox(&mut tuning.feedforward, "Gravity feedforward");
+                    for (gains, name) in tuning.gains.iter_mut().zip(JOINT_NAMES) {
+                        ui.horizontal(|ui| {
+                            ui.label(format!("{}: ", name));
+                            ui.label("Kp");
+                            ui.add(egui::DragValue::new(&mut gains.kp).clamp_range(0.0..=1e6));
+                            ui.label("Ki");
+                            ui.add(egui::DragValue::new(&mut gains.ki).clamp_range(0.0..=1e6));
+                            ui.label("Kd");
+                            ui.add(egui::DragValue::new(&mut gains.kd).clamp_range(0.0..=1e5));
+                        });
+                    }
+                    if tuning != servo.tuning {
+                        servo.tuning = tuning;
+                    }
+                    if !servo.enabled {
+                        return;
+                    }
+
+                    let error = servo.following_error(joint_state);
+                    for (index, name) in JOINT_NAMES.iter().enumerate() {
+                        ui.label(format!(
+                            "{}: following error {:.2} deg",
+                            name,
+                            error[index].to_degrees()
+                        ));
+                        let line = |angles: fn(&ServoSample) -> [f32; JOINT_COUNT]| {
+                            Line::new(Values::from_values_iter(servo.history.iter().map(
+                                |sample| Value::new(sample.0, angles(sample)[index].to_degrees()),
+                            )))
+                        };
+                        Plot::new(format!("servo_{}", index))
+                            .height(80.0)
+                            .allow_drag(false)
+                            .allow_zoom(false)
+                            .show(ui, |plot| {
+                                plot.line(line(|sample| sample.1).name("setpoint"));
+                                plot.line(line(|sample| sample.2).name("actual"));
+                            });
+                    }
+                });
+
+                ui.separator();
+                egui::CollapsingHeader::new("Dynamics").show(ui, |ui| {
+                    let (dynamics, torques) = match controls.dynamics.get(robot) {
+                        Ok(robot) => robot,
+                        Err(_) => return,
+                    };
+                    for (index, name) in JOINT_NAMES.iter().enumerate() {
+                        let limit = dynamics.motor_limits[index];
+                        let torque = torques.current[index];
+                        let text = format!("{}: {:.0} / {:.0} Nm", name, torque, limit);
+                        if torque.abs() > limit {
+                            ui.colored_label(egui::Color32::from_rgb(255, 80, 80), text);
+                        } else {
+                            ui.label(text);
+                        }
+                        let values = torques
+                            .history
+                            .iter()
+                            .map(|(time, torques)| Value::new(*time, torques[index]));
+                        Plot::new(format!("torque_{}", index))
+                            .height(80.0)
+                            .allow_drag(false)
+                            .allow_zoom(false)
+                            .include_y(limit)
+                            .include_y(-limit)
+                            .show(ui, |plot| {
+                                plot.line(Line::new(Values::from_values_iter(values)));
+                                for bound in [limit, -limit] {
+                                    plot.hline(
+                                        HLine::new(bound)
+                                            .color(egui::Color32::from_rgb(255, 80, 80)),
+                                    );
+                                }
+                            });
+                    }
+                });
+            }
+
+            ui.separator();
+            egui::CollapsingHeader::new("Camera").show(ui, |ui| {
+                camera_section(ui, &mut ui_state, &mut display_controls);
             });
-            ui_state.prev_value3 = ui_state.value3;
-            ui.add(egui::Slider::new(&mut ui_state.value3, -55.0..=10.0));
-            transform3.rotate(Quat::from_rotation_x(
-                (ui_state.value3 - ui_state.prev_value3).to_radians(),
-            ));
 
-            ui.horizontal(|ui| {
-                ui.label("Upper Arm: ");
+            ui.separator();
+            egui::CollapsingHeader::new("TCP trail").show(ui, |ui| {
+                let settings = &mut *display_controls.trail;
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut settings.enabled, "Show");
+                    ui.checkbox(&mut settings.joints, "Joint origins");
+                    if ui.button("Clear").clicked() {
+                        display_controls.clear_trails.send(ClearTrails);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Fade after: ");
+                    ui.add(
+                        egui::DragValue::new(&mut settings.duration)
+                            .speed(0.1)
+                            .clamp_range(0.5..=60.0)
+                            .suffix(" s"),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Red at: ");
+                    ui.add(
+                        egui::DragValue::new(&mut settings.max_speed)
+                            .speed(0.01)
+                            .clamp_range(0.05..=5.0)
+                            .suffix(" m/s"),
+                    );
+                });
+            });
+
+            ui.separator();
+            egui::CollapsingHeader::new("Gizmos").show(ui, |ui| {
+                let settings = &mut *display_controls.gizmos;
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut settings.frames, "Joint frames");
+                    ui.checkbox(&mut settings.limits, "Joint limits");
+                });
+                ui.add(egui::Slider::new(&mut settings.size, 0.05..=1.0).text("m"));
+            });
+
+            ui.separator();
+            egui::CollapsingHeader::new("Grids").show(ui, |ui| {
+                grids_section(ui, &mut display_controls, &frames);
+            });
+
+            ui.separator();
+            egui::CollapsingHeader::new("Coordination").show(ui, |ui| {
+                coordination_section(ui, &mut program_controls, &names);
+            });
+
+            ui.separator();
+            egui::CollapsingHeader::new("Conveyor").show(ui, |ui| {
+                for mut conveyor in conveyor_controls.conveyors.iter_mut() {
+                    ui.add(egui::Slider::new(&mut conveyor.speed, 0.0..=1.0).text("belt m/s"));
+                }
+                for mut spawner in conveyor_controls.spawners.iter_mut() {
+                    ui.checkbox(&mut spawner.enabled, "Spawn parts");
+                    let mut interval = spawner.timer.duration().as_secs_f32();
+                    if ui
+                        .add(egui::Slider::new(&mut interval, 0.5..=20.0).text("interval s"))
+                        .changed()
+                    {
+                        spawner
+                            .timer
+                            .set_duration(Duration::from_secs_f32(interval));
+                    }
+                }
+                for sensor in conveyor_controls.sensors.iter() {
+                    ui.label(format!(
+                        "Sensor {}: {}",
+                        sensor.name,
+                        if sensor.active {
+                            "part present"
+                        } else {
+                            "empty"
+                        }
+                    ));
+                }
             });
-            ui_state.prev_value4 = ui_state.value4;
-            ui.add(egui::Slider::new(&mut ui_state.value4, -20.0..=40.0));
-            transform4.rotate(Quat::from_rotation_x(
-                (ui_state.value4 - ui_state.prev_value4).to_radians(),
-            ));
+        });
+
+    if ghost.target != ghost_target || ghost.robot != controls.active.entity {
+        ghost.robot = controls.active.entity;
+        ghost.target = ghost_target;
+    }
+}
+
+fn poses_section(
+    ui: &mut egui::Ui,
+    ui_state: &mut UiState,
+    library: &mut PoseLibrary,
+    pose_move: &mut PoseMove,
+    cartesian_move: &mut CartesianMove,
+    joint_state: &JointState,
+) {
+    let current = joint_state.angles.map(f32::to_degrees);
+    let mut remove = None;
+    for (index, pose) in library.poses.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.label(&pose.name);
+            // Moves there, the sliders above follow the joints
+            if ui.button("Go").clicked() {
+                pose_move.target = Some(pose.joints);
+                cartesian_move.target = None;
+            }
+            if ui.button("Update").clicked() {
+                pose.joints = current;
+            }
+            if ui.small_button("x").clicked() {
+                remove = Some(index);
+            }
+        });
+    }
+    if let Some(index) = remove {
+        library.poses.remove(index);
+    }
+    ui.horizontal(|ui| {
+        ui.text_edit_singleline(&mut ui_state.pose_name);
+        let name = ui_state.pose_name.trim();
+        if ui.button("Save current").clicked() && !name.is_empty() {
+            library.store(name, current);
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.add(
+            egui::DragValue::new(&mut pose_move.speed)
+                .speed(1.0)
+                .clamp_range(1.0..=180.0)
+                .suffix(" deg/s"),
+        );
+        if pose_move.target.is_some() && ui.button("Stop").clicked() {
+            pose_move.target = None;
+        }
+    });
+    ui.horizontal(|ui| {
+        if ui.button("Save").clicked() {
+            ui_state.poses_status = match library.save(POSES_FILE) {
+                Ok(()) => format!("Saved to {}", POSES_FILE),
+                Err(err) => format!("Save failed: {}", err),
+            };
+        }
+        if ui.button("Load").clicked() {
+            ui_state.poses_status = match PoseLibrary::load(POSES_FILE) {
+                Ok(loaded) => {
+                    *library = loaded;
+                    format!("Loaded {}", POSES_FILE)
+                }
+                Err(err) => format!("Load failed: {}", err),
+            };
+        }
+    });
+    if !ui_state.poses_status.is_empty() {
+        ui.label(&ui_state.poses_status);
+    }
+}
 
+// Instruction list of the active robot, the selected waypoint is previewed as a ghost
+fn program_section(
+    ui: &mut egui::Ui,
+    ui_state: &mut UiState,
+    program: &mut Mut<Program>,
+    poses: &PoseLibrary,
+    joint_state: &JointState,
+    ghost_target: &mut Option<GhostTarget>,
+) {
+    let mut remove = None;
+    for (index, instruction) in program.instructions.iter().enumerate() {
+        ui.horizontal(|ui| {
+            let marker = if program.running && index == program.current {
+                ">"
+            } else {
+                " "
+            };
+            let selected = ui_state.selected_instruction == Some(index);
+            let text = format!("{} {}. {}", marker, index + 1, instruction.label());
+            if ui.selectable_label(selected, text).clicked() {
+                ui_state.selected_instruction = if selected { None } else { Some(index) };
+            }
+            if ui.small_button("x").clicked() {
+                remove = Some(index);
+            }
+        });
+    }
+    if let Some(index) = remove {
+        program.instructions.remove(index);
+        ui_state.selected_instruction = None;
+    }
+
+    // The selected waypoint can be edited and is previewed from the one before it
+    let selected = ui_state.selected_instruction;
+    let from = selected.and_then(|selected| {
+        program.instructions[..selected.min(program.instructions.len())]
+            .iter()
+            .rev()
+            .find_map(|instruction| match instruction {
+                Instruction::MoveJoints { joints, .. } => Some(*joints),
+                Instruction::MovePose { name, .. } => poses.get(name).map(|pose| pose.joints),
+                _ => None,
+            })
+    });
+    let running = program.running;
+    let waypoint = selected.and_then(|index| program.instructions.get_mut(index));
+    if let Some(Instruction::MoveJoints { joints, speed }) = waypoint {
+        ui.label("Waypoint:");
+        ui.horizontal(|ui| {
+            for (index, angle) in joints.iter_mut().enumerate() {
+                let (min, max) = JOINT_LIMITS[index];
+                ui.add(
+                    egui::DragValue::new(angle)
+                        .speed(0.5)
+                        .clamp_range(min..=max),
+                );
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::DragValue::new(speed)
+                    .speed(1.0)
+                    .clamp_range(1.0..=180.0)
+                    .suffix(" deg/s"),
+            );
+            if ui.button("Set to current pose").clicked() {
+                *joints = joint_state.angles.map(f32::to_degrees);
+            }
+        });
+        if !running {
+            *ghost_target = Some(GhostTarget::Joints { from, to: *joints });
+        }
+    } else if let Some(Instruction::MovePose { name, speed }) = waypoint {
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("waypoint_pose")
+                .selected_text(name.as_str())
+                .show_ui(ui, |ui| {
+                    for pose in poses.poses.iter() {
+                        ui.selectable_value(name, pose.name.clone(), &pose.name);
+                    }
+                });
+            ui.add(
+                egui::DragValue::new(speed)
+                    .speed(1.0)
+                    .clamp_range(1.0..=180.0)
+                    .suffix(" deg/s"),
+            );
+        });
+        match poses.get(name) {
+            Some(pose) if !running => {
+                *ghost_target = Some(GhostTarget::Joints {
+                    from,
+                    to: pose.joints,
+                });
+            }
+            Some(_) => {}
+            None => {
+                ui.label(format!("No pose named {}", name));
+            }
+        }
+    }
+
+    ui.horizontal(|ui| {
+        ui.add(
+            egui::DragValue::new(&mut ui_state.program_speed)
+                .speed(1.0)
+                .clamp_range(1.0..=180.0)
+                .suffix(" deg/s"),
+        );
+        if ui.button("Add pose").clicked() {
+            program.instructions.push(Instruction::MoveJoints {
+                joints: joint_state.angles.map(f32::to_degrees),
+                speed: ui_state.program_speed,
+            });
+        }
+    });
+    // Moves to a library pose by name, so updating the pose updates the program
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source("program_pose")
+            .selected_text(ui_state.pose_name.as_str())
+            .show_ui(ui, |ui| {
+                for pose in poses.poses.iter() {
+                    ui.selectable_value(&mut ui_state.pose_name, pose.name.clone(), &pose.name);
+                }
+            });
+        if ui.button("Add named pose").clicked() {
+            program.instructions.push(Instruction::MovePose {
+                name: ui_state.pose_name.trim().to_string(),
+                speed: ui_state.program_speed,
+            });
+        }
+    });
+    ui.horizontal(|ui| {
+        if ui.button("Close").clicked() {
+            program
+                .instructions
+                .push(Instruction::Gripper { close: true });
+        }
+        if ui.button("Open").clicked() {
+            program
+                .instructions
+                .push(Instruction::Gripper { close: false });
+        }
+        ui.add(
+            egui::DragValue::new(&mut ui_state.wait_seconds)
+                .speed(0.1)
+                .clamp_range(0.0..=60.0)
+                .suffix(" s"),
+        );
+        if ui.button("Wait").clicked() {
+            program.instructions.push(Instruction::Wait {
+                seconds: ui_state.wait_seconds,
+            });
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.label("Signal/zone: ");
+        ui.text_edit_singleline(&mut ui_state.signal_name);
+    });
+    let name = ui_state.signal_name.clone();
+    ui.horizontal(|ui| {
+        if ui.button("Set").clicked() {
+            program.instructions.push(Instruction::SetSignal {
+                name: name.clone(),
+                value: true,
+            });
+        }
+        if ui.button("Reset").clicked() {
+            program.instructions.push(Instruction::SetSignal {
+                name: name.clone(),
+                value: false,
+            });
+        }
+        if ui.button("Wait signal").clicked() {
+            program.instructions.push(Instruction::WaitSignal {
+                name: name.clone(),
+                value: true,
+            });
+        }
+        if ui.button("Wait sensor").clicked() {
+            program
+                .instructions
+                .push(Instruction::WaitSensor { name: name.clone() });
+        }
+    });
+    ui.horizontal(|ui| {
+        if ui.button("Enter zone").clicked() {
+            program
+                .instructions
+                .push(Instruction::EnterZone { name: name.clone() });
+        }
+        if ui.button("Leave zone").clicked() {
+            program
+                .instructions
+                .push(Instruction::LeaveZone { name: name.clone() });
+        }
+    });
+
+    ui.horizontal(|ui| {
+        if ui.button("Run").clicked() {
+            program.start();
+        }
+        if ui.button("Stop").clicked() {
+            program.stop();
+        }
+        ui.checkbox(&mut program.looping, "Loop");
+    });
+    if let Some(reason) = &program.blocked {
+        ui.label(format!("Waiting for {}", reason));
+    }
+}
+
+fn camera_section(ui: &mut egui::Ui, ui_state: &mut UiState, controls: &mut DisplayControls) {
+    let settings = &mut *controls.camera;
+    ui.horizontal(|ui| {
+        ui.selectable_value(&mut settings.mode, CameraMode::Fly, "Fly");
+        ui.selectable_value(&mut settings.mode, CameraMode::Orbit, "Orbit");
+        ui.checkbox(&mut settings.orthographic, "Orthographic");
+    });
+    ui.horizontal(|ui| {
+        for preset in ViewPreset::ALL {
+            if ui.button(preset.name()).clicked() {
+                controls.camera_commands.send(CameraCommand::Preset(preset));
+            }
+        }
+    });
+    ui.horizontal(|ui| {
+        if ui.button("Focus base").clicked() {
+            controls.camera_commands.send(CameraCommand::FocusBase);
+        }
+        if ui.button("Focus TCP").clicked() {
+            controls.camera_commands.send(CameraCommand::FocusTcp);
+        }
+    });
+    let follow_name = |follow: Option<FollowTarget>| match follow {
+        Some(target) => target.name(),
+        None => "Off",
+    };
+    egui::ComboBox::from_label("Follow")
+        .selected_text(follow_name(settings.follow))
+        .show_ui(ui, |ui| {
+            let targets = std::iter::once(None)
+                .chain(std::iter::once(Some(FollowTarget::Tcp)))
+                .chain((0..JOINT_COUNT).map(|index| Some(FollowTarget::Link(index))));
+            for target in targets {
+                ui.selectable_value(&mut settings.follow, target, follow_name(target));
+            }
+        });
+    match settings.mode {
+        CameraMode::Fly => ui.label("WASD to move, ESC frees the cursor"),
+        CameraMode::Orbit => ui.label("Right drag orbits, middle or Shift + right drag pans"),
+    };
+
+    let viewports = &mut *controls.viewports;
+    ui.add(egui::Slider::new(&mut viewports.count, 1..=4).text("viewports"));
+    for index in 0..viewports.count.min(viewports.views.len()) {
+        let view = &mut viewports.views[index];
+        egui::ComboBox::from_id_source(("viewport", index))
+            .selected_text(view.name())
+            .show_ui(ui, |ui| {
+                for option in ViewportView::ALL {
+                    ui.selectable_value(view, option, option.name());
+                }
+            });
+    }
+
+    ui.label("Bookmarks, saved with the scene:");
+    let mut removed = None;
+    for (index, bookmark) in controls.bookmarks.list.iter().enumerate() {
+        ui.horizontal(|ui| {
+            if ui.button(&bookmark.name).clicked() {
+                controls.camera_commands.send(CameraCommand::Recall(index));
+            }
+            if ui.small_button("x").clicked() {
+                removed = Some(index);
+            }
+        });
+    }
+    if let Some(index) = removed {
+        controls.bookmarks.list.remove(index);
+    }
+    ui.horizontal(|ui| {
+        ui.text_edit_singleline(&mut ui_state.bookmark_name);
+        if ui.button("Add").clicked() && !ui_state.bookmark_name.is_empty() {
+            controls
+                .camera_commands
+                .send(CameraCommand::AddBookmark(ui_state.bookmark_name.clone()));
+            ui_state.bookmark_name = format!("View {}", controls.bookmarks.list.len() + 2);
+        }
+    });
+}
+
+fn grids_section(ui: &mut egui::Ui, controls: &mut DisplayControls, frames: &Frames) {
+    let placements = grid_placements(frames);
+    for (entity, mut reference, mut grid, mut visibility) in controls.grids.iter_mut() {
+        egui::CollapsingHeader::new(reference.name.as_str())
+            .id_source(entity)
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut visibility.is_visible, "Show");
+                    ui.text_edit_singleline(&mut reference.name);
+                    if ui.button("Remove").clicked() {
+                        controls.grid_commands.send(GridCommand::Remove(entity));
+                    }
+                });
+                egui::ComboBox::from_label("Placement")
+                    .selected_text(reference.placement.name())
+                    .show_ui(ui, |ui| {
+                        for placement in placements.iter() {
+                            ui.selectable_value(
+                                &mut reference.placement,
+                                placement.clone(),
+                                placement.name(),
+                            );
+                        }
+                    });
+                grid_settings(ui, &mut grid);
+            });
+    }
+    if ui.button("Add grid").clicked() {
+        controls.grid_commands.send(GridCommand::Add);
+    }
+}
+
+fn coordination_section(
+    ui: &mut egui::Ui,
+    controls: &mut ProgramControls,
+    names: &[(Entity, String)],
+) {
+    ui.horizontal(|ui| {
+        if ui.button("Run all").clicked() {
+            for mut program in controls.programs.iter_mut() {
+                program.start();
+            }
+        }
+        if ui.button("Stop all").clicked() {
+            for mut program in controls.programs.iter_mut() {
+                program.stop();
+            }
+        }
+    });
+
+    let coordination = &mut *controls.coordination;
+    ui.label("Signals:");
+    for (name, value) in coordination.signals.iter_mut() {
+        ui.checkbox(value, name.as_str());
+    }
+
+    ui.label("Zones:");
+    let mut remove = None;
+    for (index, zone) in coordination.zones.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut zone.name);
+            if ui.small_button("x").clicked() {
+                remove = Some(index);
+            }
+        });
+        for (label, corner) in [("Min: ", &mut zone.min), ("Max: ", &mut zone.max)] {
             ui.horizontal(|ui| {
-                ui.label("Wrist: ");
+                ui.label(label);
+                ui.add(egui::DragValue::new(&mut corner.x).speed(0.01));
+                ui.add(egui::DragValue::new(&mut corner.y).speed(0.01));
+                ui.add(egui::DragValue::new(&mut corner.z).speed(0.01));
+            });
+        }
+        let owner = zone.owner.map_or("free", |robot| robot_name(names, robot));
+        if zone.violated() {
+            ui.colored_label(
+                egui::Color32::from_rgb(255, 80, 80),
+                format!("Held by {}, violated", owner),
+            );
+        } else {
+            ui.label(format!("Held by {}", owner));
+        }
+    }
+    if let Some(index) = remove {
+        coordination.zones.remove(index);
+    }
+    if ui.button("Add zone").clicked() {
+        let name = format!("zone{}", coordination.zones.len() + 1);
+        coordination.zones.push(Zone::new(&name));
+    }
+
+    if !coordination.messages.is_empty() {
+        ui.label("Events:");
+        for message in coordination.messages.iter().rev() {
+            ui.label(message);
+        }
+        if ui.button("Clear").clicked() {
+            coordination.messages.clear();
+        }
+    }
+}
+
+fn robot_name(names: &[(Entity, String)], robot: Entity) -> &str {
+    names
+        .iter()
+        .find(|(entity, _)| *entity == robot)
+        .map_or("?", |(_, name)| name.as_str())
+}
+
+fn grid_settings(ui: &mut egui::Ui, grid: &mut InfiniteGrid) {
+    ui.horizontal(|ui| {
+        ui.label("Cell: ");
+        let factor = grid.unit.factor();
+        let mut size = grid.cell_size / factor;
+        ui.add(
+            egui::DragValue::new(&mut size)
+                .speed(0.1)
+                .clamp_range(0.001 / factor..=100.0 / factor),
+        );
+        grid.cell_size = size * factor;
+        egui::ComboBox::from_id_source("grid_unit")
+            .selected_text(grid.unit.name())
+            .show_ui(ui, |ui| {
+                for unit in GridUnit::ALL {
+                    ui.selectable_value(&mut grid.unit, unit, unit.name());
+                }
             });
-            ui_state.prev_value5 = ui_state.value5;
-            ui.add(egui::Slider::new(&mut ui_state.value5, -180.0..=180.0));
-            transform5.rotate(Quat::from_rotation_z(
-                (ui_state.value5 - ui_state.prev_value5).to_radians(),
-            ));
+    });
+    ui.horizontal(|ui| {
+        ui.label("Minor per major: ");
+        ui.add(egui::DragValue::new(&mut grid.subdivisions).clamp_range(1..=100));
+    });
+    ui.add(egui::Slider::new(&mut grid.line_width, 0.5..=5.0).text("line width (px)"));
+    ui.add(
+        egui::Slider::new(&mut grid.fade_distance, 1.0..=500.0)
+            .logarithmic(true)
+            .text("fade distance (m)"),
+    );
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut grid.shadows, "Shadows");
+        ui.add(
+            egui::DragValue::new(&mut grid.shadow_softness)
+                .speed(0.01)
+                .clamp_range(0.01..=2.0)
+                .prefix("contact ")
+                .suffix(" m"),
+        );
+    });
+    for (label, color) in [
+        ("Minor lines", &mut grid.minor_line_color),
+        ("Major lines", &mut grid.major_line_color),
+        ("X axis", &mut grid.x_axis_color),
+        ("Z axis", &mut grid.z_axis_color),
+        ("Shadow", &mut grid.shadow_color),
+    ] {
+        ui.horizontal(|ui| {
+            let mut rgba = color.as_rgba_f32();
+            if ui.color_edit_button_rgba_unmultiplied(&mut rgba).changed() {
+                *color = Color::rgba(rgba[0], rgba[1], rgba[2], rgba[3]);
+            }
+            ui.label(label);
         });
+    }
+}
+
+// Position and RPY in degrees, like the user frames
+fn transform_edit(ui: &mut egui::Ui, transform: &mut Transform) {
+    let (x, y, z) = transform.rotation.to_euler(EulerRot::XYZ);
+    let mut rotation = Vec3::new(x.to_degrees(), y.to_degrees(), z.to_degrees());
+    let before = rotation;
+    ui.horizontal(|ui| {
+        ui.label("XYZ: ");
+        ui.add(egui::DragValue::new(&mut transform.translation.x).speed(0.01));
+        ui.add(egui::DragValue::new(&mut transform.translation.y).speed(0.01));
+        ui.add(egui::DragValue::new(&mut transform.translation.z).speed(0.01));
+    });
+    ui.horizontal(|ui| {
+        ui.label("RPY: ");
+        ui.add(egui::DragValue::new(&mut rotation.x).speed(1.0));
+        ui.add(egui::DragValue::new(&mut rotation.y).speed(1.0));
+        ui.add(egui::DragValue::new(&mut rotation.z).speed(1.0));
+    });
+    // Only rebuilt when edited, the Euler round trip is not exact
+    if rotation != before {
+        transform.rotation = Quat::from_euler(
+            EulerRot::XYZ,
+            rotation.x.to_radians(),
+            rotation.y.to_radians(),
+            rotation.z.to_radians(),
+        );
+    }
+}
+
+fn frame_list(ui: &mut egui::Ui, label: &str, list: &mut Vec<Frame>, active: &mut usize) {
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_label(label)
+            .selected_text(list.get(*active).map_or("?", |frame| frame.name.as_str()))
+            .show_ui(ui, |ui| {
+                for (index, frame) in list.iter().enumerate() {
+                    ui.selectable_value(active, index, frame.name.as_str());
+                }
+            });
+        if ui.button("+").clicked() {
+            list.push(Frame::new(&format!("frame{}", list.len())));
+            *active = list.len() - 1;
+        }
+        // The built-in first frame cannot be removed or edited
+        if *active > 0 && *active < list.len() && ui.button("-").clicked() {
+            list.remove(*active);
+            *active -= 1;
+        }
+    });
+    let frame = match list.get_mut(*active) {
+        Some(frame) if *active > 0 => frame,
+        _ => return,
+    };
+    ui.horizontal(|ui| {
+        ui.label("Name: ");
+        ui.text_edit_singleline(&mut frame.name);
+    });
+    ui.horizontal(|ui| {
+        ui.label("XYZ: ");
+        ui.add(egui::DragValue::new(&mut frame.translation.x).speed(0.01));
+        ui.add(egui::DragValue::new(&mut frame.translation.y).speed(0.01));
+        ui.add(egui::DragValue::new(&mut frame.translation.z).speed(0.01));
+    });
+    ui.horizontal(|ui| {
+        ui.label("RPY: ");
+        ui.add(egui::DragValue::new(&mut frame.rotation.x).speed(1.0));
+        ui.add(egui::DragValue::new(&mut frame.rotation.y).speed(1.0));
+        ui.add(egui::DragValue::new(&mut frame.rotation.z).speed(1.0));
+    });
 }
@@ -0,0 +1,291 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    kinematics::JOINT_COUNT,
+    robot::{Joint, FLANGE_OFFSET},
+};
+
+// Objects closer than this to the TCP are picked up when the tool closes
+pub const GRASP_RADIUS: f32 = 0.2;
+
+const FINGER_OPEN: f32 = 0.11;
+const FINGER_CLOSED: f32 = 0.04;
+const FINGER_SPEED: f32 = 0.3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ToolKind {
+    None,
+    ParallelGripper,
+    VacuumCup,
+    Custom,
+}
+
+impl ToolKind {
+    pub const ALL: [ToolKind; 4] = [
+        ToolKind::None,
+        ToolKind::ParallelGripper,
+        ToolKind::VacuumCup,
+        ToolKind::Custom,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToolKind::None => "None",
+            ToolKind::ParallelGripper => "Parallel gripper",
+            ToolKind::VacuumCup => "Vacuum cup",
+            ToolKind::Custom => "Custom OBJ",
+        }
+    }
+}
+
+// Tool mounted on a robot's wrist flange, the tool is remounted whenever this changes
+#[derive(Component, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolSettings {
+    pub kind: ToolKind,
+    pub custom_mesh: String,
+    pub custom_tcp: Vec3,
+}
+
+impl Default for ToolSettings {
+    fn default() -> Self {
+        Self {
+            kind: ToolKind::ParallelGripper,
+            custom_mesh: "models/tool.obj".to_string(),
+            custom_tcp: Vec3::new(0.0, 0.0, 0.2),
+        }
+    }
+}
+
+#[derive(Component, Debug)]
+pub struct Tool {
+    pub robot: Entity,
+    pub kind: ToolKind,
+    // TCP offset from the flange in the tool frame
+    pub tcp: Vec3,
+    pub closed: bool,
+    pub grasped: Option<Entity>,
+}
+
+#[derive(Component, Debug)]
+pub struct Finger {
+    pub side: f32,
+}
+
+// Scene objects that can be picked up by the tool
+#[derive(Component, Debug)]
+pub struct Graspable;
+
+// Sent to the tool of the given robot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GripperCommand {
+    Close(Entity),
+    Open(Entity),
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn mount_tool(
+    mut commands: Commands,
+    robots: Query<(Entity, &ToolSettings), Changed<ToolSettings>>,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    joints: Query<(Entity, &Joint)>,
+    tools: Query<(Entity, &Tool)>,
+    objects: Query<&GlobalTransform, With<Graspable>>,
+) {
+    for (robot, settings) in robots.iter() {
+        // The tool is mounted on the last link
+        let wrist = joints
+            .iter()
+            .find(|(_, joint)| joint.robot == robot && joint.index == JOINT_COUNT - 1)
+            .map(|(wrist, _)| wrist);
+        if let Some(wrist) = wrist {
+            for (entity, tool) in tools.iter().filter(|(_, tool)| tool.robot == robot) {
+                if let Some(object) = tool.grasped {
+                    if let Ok(global) = objects.get(object) {
+                        release(&mut commands, entity, object, global);
+                    }
+                }
+                commands.entity(entity).despawn_recursive();
+            }
+            spawn_tool(
+                &mut commands,
+                &asset_server,
+                &mut meshes,
+                &mut materials,
+                robot,
+                wrist,
+                settings,
+            );
+        }
+    }
+}
+
+fn spawn_tool(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    robot: Entity,
+    wrist: Entity,
+    settings: &ToolSettings,
+) {
+    if settings.kind == ToolKind::None {
+        return;
+    }
+
+    let material = materials.add(Color::rgb(0.3, 0.3, 0.35).into());
+    let flange = Transform::from_translation(FLANGE_OFFSET);
+    let tool = match settings.kind {
+        ToolKind::ParallelGripper => commands
+            .spawn_bundle(PbrBundle {
+                mesh: meshes.add(Mesh::from(shape::Box::new(0.3, 0.12, 0.08))),
+                material: material.clone(),
+                transform: flange,
+                ..Default::default()
+            })
+            .insert(Tool {
+                robot,
+                kind: settings.kind,
+                tcp: Vec3::new(0.0, 0.0, 0.25),
+                closed: false,
+                grasped: None,
+            })
+            .with_children(|parent| {
+                for side in [-1.0, 1.0] {
+                    parent
+                        .spawn_bundle(PbrBundle {
+                            mesh: meshes.add(Mesh::from(shape::Box::new(0.04, 0.08, 0.22))),
+                            material: material.clone(),
+                            transform: Transform::from_xyz(side * FINGER_OPEN, 0.0, 0.15),
+                            ..Default::default()
+                        })
+                        .insert(Finger { side });
+                }
+            })
+            .id(),
+        ToolKind::VacuumCup => commands
+            .spawn_bundle(PbrBundle {
+                mesh: meshes.add(Mesh::from(shape::Box::new(0.06, 0.06, 0.16))),
+                material: material.clone(),
+                transform: flange,
+                ..Default::default()
+            })
+            .insert(Tool {
+                robot,
+                kind: settings.kind,
+                tcp: Vec3::new(0.0, 0.0, 0.16),
+                closed: false,
+                grasped: None,
+            })
+            .with_children(|parent| {
+                parent.spawn_bundle(PbrBundle {
+                    mesh: meshes.add(Mesh::from(shape::Capsule {
+                        radius: 0.07,
+                        depth: 0.0,
+                        ..Default::default()
+                    })),
+                    material: material.clone(),
+                    transform: Transform::from_xyz(0.0, 0.0, 0.12)
+                        .with_scale(Vec3::new(1.0, 1.0, 0.4)),
+                    ..Default::default()
+                });
+            })
+            .id(),
+        _ => commands
+            .spawn_bundle(PbrBundle {
+                mesh: asset_server.load(settings.custom_mesh.as_str()),
+                material: material.clone(),
+                transform: flange,
+                ..Default::default()
+            })
+            .insert(Tool {
+                robot,
+                kind: settings.kind,
+                tcp: settings.custom_tcp,
+                closed: false,
+                grasped: None,
+            })
+            .id(),
+    };
+    commands.entity(wrist).add_child(tool);
+}
+
+// Objects lying free, a part held by another tool cannot be grasped
+type LooseObject = (With<Graspable>, Without<Parent>);
+
+pub fn operate_gripper(
+    mut commands: Commands,
+    mut events: EventReader<GripperCommand>,
+    mut tools: Query<(Entity, &mut Tool, &GlobalTransform)>,
+    objects: Query<(Entity, &GlobalTransform), LooseObject>,
+) {
+    for command in events.iter() {
+        let robot = match *command {
+            GripperCommand::Close(robot) | GripperCommand::Open(robot) => robot,
+        };
+        for (entity, mut tool, tool_global) in tools.iter_mut() {
+            if tool.robot != robot {
+                continue;
+            }
+            match command {
+                GripperCommand::Close(_) if !tool.closed => {
+                    tool.closed = true;
+                    let tcp = tool_global.mul_vec3(tool.tcp);
+                    let nearest = objects
+                        .iter()
+                        .map(|(object, global)| (object, global, global.translation.distance(tcp)))
+                        .filter(|(_, _, distance)| *distance < GRASP_RADIUS)
+                        .min_by(|a, b| a.2.total_cmp(&b.2));
+                    if let Some((object, global, _)) = nearest {
+                        // Keep the object where it is while it becomes part of the tool
+                        let local =
+                            tool_global.compute_matrix().inverse() * global.compute_matrix();
+                        commands
+                            .entity(object)
+                            .insert(Transform::from_matrix(local));
+                        commands.entity(entity).add_child(object);
+                        tool.grasped = Some(object);
+                    }
+                }
+                GripperCommand::Open(_) if tool.closed => {
+                    tool.closed = false;
+                    if let Some(object) = tool.grasped.take() {
+                        if let Ok((_, global)) = objects.get(object) {
+                            release(&mut commands, entity, object, global);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn release(commands: &mut Commands, tool: Entity, object: Entity, global: &GlobalTransform) {
+    commands.entity(tool).remove_children(&[object]);
+    commands
+        .entity(object)
+        .insert(Transform::from_matrix(global.compute_matrix()));
+}
+
+pub fn animate_fingers(
+    time: Res<Time>,
+    tools: Query<&Tool>,
+    mut fingers: Query<(&Finger, &Parent, &mut Transform)>,
+) {
+    for (finger, parent, mut transform) in fingers.iter_mut() {
+        if let Ok(tool) = tools.get(parent.0) {
+            let opening = if tool.closed {
+                FINGER_CLOSED
+            } else {
+                FINGER_OPEN
+            };
+            let target = finger.side * opening;
+            let step = FINGER_SPEED * time.delta_seconds();
+            let delta = target - transform.translation.x;
+            transform.translation.x += delta.clamp(-step, step);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    pbr::NotShadowCaster,
+    prelude::*,
+    render::{mesh::PrimitiveTopology, view::NoFrustumCulling},
+};
+
+use crate::{
+    collision::link_points,
+    kinematics::{Manipulability, JOINT_COUNT},
+    robot::Robot,
+};
+
+// Points closer than this (m) to the last one are not recorded, a resting robot adds nothing
+const MIN_STEP: f32 = 0.002;
+// Lines are batched per speed color and fade level, StandardMaterial has no vertex colors
+const SPEED_COLORS: usize = 8;
+const FADE_LEVELS: usize = 4;
+const TRACKS: usize = JOINT_COUNT + 1;
+
+pub struct TrailSettings {
+    pub enabled: bool,
+    // Also draw the paths of the joint origins, not only the TCP
+    pub joints: bool,
+    // Seconds until a point has faded out
+    pub duration: f32,
+    // Speed in m/s drawn in the hottest color
+    pub max_speed: f32,
+}
+
+impl Default for TrailSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            joints: false,
+            duration: 5.0,
+            max_speed: 1.0,
+        }
+    }
+}
+
+pub struct ClearTrails;
+
+#[derive(Debug, Clone, Copy)]
+struct TrailPoint {
+    time: f64,
+    // Joint origins followed by the TCP, and how fast each moved to get here
+    positions: [Vec3; TRACKS],
+    speeds: [f32; TRACKS],
+}
+
+#[derive(Component, Debug, Clone, Default)]
+pub struct Trail {
+    points: VecDeque<TrailPoint>,
+}
+
+// One line mesh per speed color and fade level
+#[derive(Component, Debug, Clone, Copy)]
+pub struct TrailMesh {
+    robot: Entity,
+    bucket: usize,
+}
+
+pub struct TrailMaterials {
+    materials: Vec<Handle<StandardMaterial>>,
+}
+
+impl FromWorld for TrailMaterials {
+    fn from_world(world: &mut World) -> Self {
+        let mut assets = world.resource_mut::<Assets<StandardMaterial>>();
+        let mut materials = Vec::new();
+        for color in 0..SPEED_COLORS {
+            // Blue when slow to red at full speed
+            let hue = 240.0 * (1.0 - color as f32 / (SPEED_COLORS - 1) as f32);
+            for level in 0..FADE_LEVELS {
+                let alpha = 1.0 - level as f32 / FADE_LEVELS as f32;
+                materials.push(assets.add(StandardMaterial {
+                    base_color: Color::hsla(hue, 1.0, 0.5, alpha),
+                    alpha_mode: AlphaMode::Blend,
+                    unlit: true,
+                    ..Default::default()
+                }));
+            }
+        }
+        Self { materials }
+    }
+}
+
+pub fn line_mesh(positions: Vec<[f32; 3]>) -> Mesh {
+    // The mesh pipeline needs normals and uvs even for lines
+    let count = positions.len();
+    let mut mesh = Mesh::new(PrimitiveTopology::LineList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 1.0, 0.0]; count]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0.0, 0.0]; count]);
+    mesh
+}
+
+pub fn record_trails(
+    time: Res<Time>,
+    settings: Res<TrailSettings>,
+    mut events: EventReader<ClearTrails>,
+    mut robots: Query<(&Manipulability, &mut Trail), With<Robot>>,
+) {
+    let clear = events.iter().count() > 0;
+    let now = time.seconds_since_startup();
+    for (manipulability, mut trail) in robots.iter_mut() {
+        if clear || !settings.enabled {
+            if !trail.points.is_empty() {
+                trail.points.clear();
+            }
+            continue;
+        }
+        while matches!(trail.points.front(), Some(point) if now - point.time > settings.duration as f64)
+        {
+            trail.points.pop_front();
+        }
+        let positions = link_points(manipulability);
+        let mut speeds = [0.0; TRACKS];
+        if let Some(last) = trail.points.back() {
+            if last.positions[JOINT_COUNT].distance(positions[JOINT_COUNT]) < MIN_STEP {
+                continue;
+            }
+            let dt = (now - last.time).max(f64::EPSILON) as f32;
+            for (speed, (position, previous)) in
+                speeds.iter_mut().zip(positions.iter().zip(last.positions))
+            {
+                *speed = position.distance(previous) / dt;
+            }
+        }
+        trail.points.push_back(TrailPoint {
+            time: now,
+            positions,
+            speeds,
+        });
+    }
+}
+
+pub fn draw_trails(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<TrailSettings>,
+    materials: Res<TrailMaterials>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    robots: Query<(Entity, &Trail)>,
+    mut trail_meshes: Query<(Entity, &TrailMesh, &Handle<Mesh>, &mut Visibility)>,
+) {
+    for (entity, trail_mesh, ..) in trail_meshes.iter() {
+        if !robots.contains(trail_mesh.robot) {
+            commands.entity(entity).despawn();
+        }
+    }
+
+    // Rebuilt every frame since the points keep fading even when the robot rests
+    let now = time.seconds_since_startup();
+    let tracks = if settings.joints {
+        0..TRACKS
+    } else {
+        JOINT_COUNT..TRACKS
+    };
+    for (robot, trail) in robots.iter() {
+        let mut buckets = vec![Vec::new(); SPEED_COLORS * FADE_LEVELS];
+        for (previous, point) in trail.points.iter().zip(trail.points.iter().skip(1)) {
+            let age = ((now - point.time) as f32 / settings.duration).clamp(0.0, 1.0);
+            let level = ((age * FADE_LEVELS as f32) as usize).min(FADE_LEVELS - 1);
+            for track in tracks.clone() {
+                let speed = (point.speeds[track] / settings.max_speed).clamp(0.0, 1.0);
+                let color = (speed * (SPEED_COLORS - 1) as f32).round() as usize;
+                let bucket = &mut buckets[color * FADE_LEVELS + level];
+                bucket.push(previous.positions[track].to_array());
+                bucket.push(point.positions[track].to_array());
+            }
+        }
+
+        let mut spawned = vec![false; buckets.len()];
+        for (_, trail_mesh, handle, mut visibility) in trail_meshes.iter_mut() {
+            if trail_mesh.robot != robot {
+                continue;
+            }
+            spawned[trail_mesh.bucket] = true;
+            let positions = std::mem::take(&mut buckets[trail_mesh.bucket]);
+            visibility.is_visible = !positions.is_empty();
+            if let Some(mesh) = meshes.get_mut(handle) {
+                if !positions.is_empty() {
+                    *mesh = line_mesh(positions);
+                }
+            }
+        }
+        for (bucket, positions) in buckets.into_iter().enumerate() {
+            if spawned[bucket] || positions.is_empty() {
+                continue;
+            }
+            commands
+                .spawn_bundle(PbrBundle {
+                    mesh: meshes.add(line_mesh(positions)),
+                    material: materials.materials[bucket].clone(),
+                    ..Default::default()
+                })
+                .insert(TrailMesh { robot, bucket })
+                .insert(NoFrustumCulling)
+                .insert(NotShadowCaster);
+        }
+    }
+}
@@ -0,0 +1,424 @@
+use bevy::{
+    core_pipeline::{draw_3d_graph, node, AlphaMask3d, Opaque3d, Transparent3d},
+    prelude::*,
+    render::{
+        camera::{ExtractedCamera, RenderTarget},
+        render_graph::{Node, NodeRunError, RenderGraph, RenderGraphContext, SlotValue},
+        render_phase::RenderPhase,
+        render_resource::{
+            Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+        },
+        renderer::RenderContext,
+        texture::BevyDefault,
+        view::{ExtractedView, VisibleEntities},
+        RenderApp, RenderStage,
+    },
+};
+use bevy_egui::{egui, EguiContext};
+
+use crate::{
+    camera::{set_projection, OrbitCamera, ViewPreset},
+    kinematics::Manipulability,
+    robot::ActiveRobot,
+};
+
+pub const MAX_VIEWPORTS: usize = 4;
+const VIEWPORT_DRIVER: &str = "viewport_driver";
+// Logical pixels between the viewports
+const GAP: f32 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViewportView {
+    // Same view as the main camera
+    Main,
+    Front,
+    Side,
+    Top,
+    // Looking out along the tool axis
+    Tcp,
+}
+
+impl ViewportView {
+    pub const ALL: [ViewportView; 5] = [
+        ViewportView::Main,
+        ViewportView::Front,
+        ViewportView::Side,
+        ViewportView::Top,
+        ViewportView::Tcp,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ViewportView::Main => "Main",
+            ViewportView::Front => "Front",
+            ViewportView::Side => "Side",
+            ViewportView::Top => "Top",
+            ViewportView::Tcp => "TCP",
+        }
+    }
+
+    fn preset(&self) -> Option<ViewPreset> {
+        match self {
+            ViewportView::Front => Some(ViewPreset::Front),
+            ViewportView::Side => Some(ViewPreset::Side),
+            ViewportView::Top => Some(ViewPreset::Top),
+            ViewportView::Main | ViewportView::Tcp => None,
+        }
+    }
+}
+
+pub struct ViewportSettings {
+    // 1 shows only the main camera, 2 to 4 split the window
+    pub count: usize,
+    pub views: [ViewportView; MAX_VIEWPORTS],
+}
+
+impl Default for ViewportSettings {
+    fn default() -> Self {
+        Self {
+            count: 1,
+            views: [
+                ViewportView::Main,
+                ViewportView::Top,
+                ViewportView::Front,
+                ViewportView::Tcp,
+            ],
+        }
+    }
+}
+
+// Images the viewport cameras render into and the size in pixels the window has room for
+pub struct Viewports {
+    images: Vec<Handle<Image>>,
+    sizes: [UVec2; MAX_VIEWPORTS],
+    // Where the viewports are shown, empty while the main camera fills the window
+    cells: Vec<egui::Rect>,
+}
+
+// A cursor inside the view of one camera, in logical pixels from the bottom left like the
+// window cursor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ViewportCursor {
+    // Split viewport the cursor is in, None for the main camera
+    pub cell: Option<usize>,
+    pub position: Vec2,
+    pub size: Vec2,
+}
+
+impl Viewports {
+    // Finds the viewport under a window cursor, or keeps to `cell` while a drag started there
+    pub fn locate(
+        &self,
+        window: &Window,
+        cursor: Vec2,
+        cell: Option<usize>,
+    ) -> Option<ViewportCursor> {
+        if self.cells.is_empty() {
+            return Some(ViewportCursor {
+                cell: None,
+                position: cursor,
+                size: Vec2::new(window.width(), window.height()),
+            });
+        }
+        // egui measures from the top left
+        let point = egui::pos2(cursor.x, window.height() - cursor.y);
+        let index = cell.or_else(|| self.cells.iter().position(|rect| rect.contains(point)))?;
+        let rect = self.cells.get(index)?;
+        Some(ViewportCursor {
+            cell: Some(index),
+            position: Vec2::new(point.x - rect.min.x, rect.max.y - point.y),
+            size: Vec2::new(rect.width(), rect.height()),
+        })
+    }
+}
+
+impl FromWorld for Viewports {
+    fn from_world(world: &mut World) -> Self {
+        let mut assets = world.resource_mut::<Assets<Image>>();
+        let size = Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+        let images = (0..MAX_VIEWPORTS)
+            .map(|_| {
+                let mut image = Image {
+                    texture_descriptor: TextureDescriptor {
+                        label: Some("viewport"),
+                        size,
+                        dimension: TextureDimension::D2,
+                        format: TextureFormat::bevy_default(),
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        usage: TextureUsages::TEXTURE_BINDING
+                            | TextureUsages::COPY_DST
+                            | TextureUsages::RENDER_ATTACHMENT,
+                    },
+                    ..Default::default()
+                };
+                image.resize(size);
+                assets.add(image)
+            })
+            .collect();
+        Self {
+            images,
+            sizes: [UVec2::ONE; MAX_VIEWPORTS],
+            cells: Vec::new(),
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct ViewportCamera {
+    pub index: usize,
+}
+
+// Renders the viewport cameras into their images before the main pass, like the main
+// camera each gets its own view uniforms, so the grid and lights work in all of them
+pub struct ViewportPlugin;
+
+impl Plugin for ViewportPlugin {
+    fn build(&self, app: &mut App) {
+        let render_app = app.get_sub_app_mut(RenderApp).unwrap();
+        render_app.add_system_to_stage(RenderStage::Extract, extract_viewport_cameras);
+        let driver = ViewportDriverNode::new(&mut render_app.world);
+        let mut graph = render_app.world.resource_mut::<RenderGraph>();
+        graph.add_node(VIEWPORT_DRIVER, driver);
+        graph
+            .add_node_edge(node::MAIN_PASS_DEPENDENCIES, VIEWPORT_DRIVER)
+            .unwrap();
+        graph
+            .add_node_edge(node::CLEAR_PASS_DRIVER, VIEWPORT_DRIVER)
+            .unwrap();
+        graph
+            .add_node_edge(VIEWPORT_DRIVER, node::MAIN_PASS_DRIVER)
+            .unwrap();
+    }
+}
+
+// Bevy only extracts the active camera of each kind, so the viewports are extracted here
+fn extract_viewport_cameras(
+    mut commands: Commands,
+    windows: Res<Windows>,
+    images: Res<Assets<Image>>,
+    cameras: Query<(
+        Entity,
+        &Camera,
+        &GlobalTransform,
+        &VisibleEntities,
+        &ViewportCamera,
+    )>,
+) {
+    for (entity, camera, transform, visible_entities, viewport) in cameras.iter() {
+        let size = match camera.target.get_physical_size(&windows, &images) {
+            Some(size) => size,
+            None => continue,
+        };
+        commands.get_or_spawn(entity).insert_bundle((
+            ExtractedCamera {
+                target: camera.target.clone(),
+                physical_size: Some(size),
+            },
+            ExtractedView {
+                projection: camera.projection_matrix,
+                transform: *transform,
+                width: size.x,
+                height: size.y,
+                near: camera.near,
+                far: camera.far,
+            },
+            visible_entities.clone(),
+            *viewport,
+            RenderPhase::<Opaque3d>::default(),
+            RenderPhase::<AlphaMask3d>::default(),
+            RenderPhase::<Transparent3d>::default(),
+        ));
+    }
+}
+
+struct ViewportDriverNode {
+    query: QueryState<Entity, With<ViewportCamera>>,
+}
+
+impl ViewportDriverNode {
+    fn new(world: &mut World) -> Self {
+        Self {
+            query: QueryState::new(world),
+        }
+    }
+}
+
+impl Node for ViewportDriverNode {
+    fn update(&mut self, world: &mut World) {
+        self.query.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        graph: &mut RenderGraphContext,
+        _render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        for camera in self.query.iter_manual(world) {
+            graph.run_sub_graph(draw_3d_graph::NAME, vec![SlotValue::Entity(camera)])?;
+        }
+        Ok(())
+    }
+}
+
+// Area of the window right of the side panel, split into one cell per viewport
+fn viewport_cells(area: egui::Rect, count: usize) -> Vec<egui::Rect> {
+    let rows = if count > 2 { 2 } else { 1 };
+    let size = egui::vec2(area.width() / 2.0, area.height() / rows as f32);
+    (0..count)
+        .map(|index| {
+            let min = area.min + egui::vec2((index % 2) as f32, (index / 2) as f32) * size;
+            egui::Rect::from_min_size(min, size).shrink(GAP / 2.0)
+        })
+        .collect()
+}
+
+// Runs after the side panel so the free part of the window is known
+pub fn show_viewports(
+    mut egui_ctx: ResMut<EguiContext>,
+    settings: Res<ViewportSettings>,
+    mut viewports: ResMut<Viewports>,
+) {
+    if settings.count < 2 {
+        viewports.cells.clear();
+        return;
+    }
+    let textures: Vec<egui::TextureId> = viewports
+        .images
+        .iter()
+        .map(|image| egui_ctx.add_image(image.clone_weak()))
+        .collect();
+    let ctx = egui_ctx.ctx_mut();
+    let pixels_per_point = ctx.pixels_per_point();
+    let cells = viewport_cells(ctx.available_rect(), settings.count);
+    viewports.cells = cells.clone();
+    // Not interactable, so the camera can still be moved with the mouse over the viewports
+    egui::Area::new("viewports")
+        .order(egui::Order::Background)
+        .interactable(false)
+        .fixed_pos(egui::Pos2::ZERO)
+        .show(ctx, |ui| {
+            for (index, cell) in cells.iter().enumerate() {
+                ui.put(*cell, egui::Image::new(textures[index], cell.size()));
+                ui.painter().text(
+                    cell.min + egui::vec2(6.0, 4.0),
+                    egui::Align2::LEFT_TOP,
+                    settings.views[index].name(),
+                    egui::FontId::proportional(14.0),
+                    egui::Color32::WHITE,
+                );
+                let size = (cell.size() * pixels_per_point).round();
+                viewports.sizes[index] = UVec2::new(size.x.max(1.0) as u32, size.y.max(1.0) as u32);
+            }
+        });
+}
+
+type MainCamera = (With<OrbitCamera>, Without<ViewportCamera>);
+
+// Keeps one camera per shown viewport, sized to its cell and placed for its view
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn update_viewports(
+    mut commands: Commands,
+    settings: Res<ViewportSettings>,
+    viewports: Res<Viewports>,
+    mut images: ResMut<Assets<Image>>,
+    active: Res<ActiveRobot>,
+    robots: Query<&Manipulability>,
+    main: Query<(&Transform, &OrbitCamera, Option<&OrthographicProjection>), MainCamera>,
+    mut cameras: Query<(
+        Entity,
+        &ViewportCamera,
+        &mut Transform,
+        &mut Camera,
+        Option<&mut OrthographicProjection>,
+    )>,
+) {
+    let count = if settings.count < 2 {
+        0
+    } else {
+        settings.count.min(MAX_VIEWPORTS)
+    };
+    let mut spawned = [false; MAX_VIEWPORTS];
+    for (entity, viewport, ..) in cameras.iter() {
+        if viewport.index < count {
+            spawned[viewport.index] = true;
+        } else {
+            commands.entity(entity).despawn();
+        }
+    }
+    for (index, _) in spawned
+        .iter()
+        .enumerate()
+        .filter(|(index, spawned)| *index < count && !**spawned)
+    {
+        let mut bundle = PerspectiveCameraBundle::<ViewportCamera>::new();
+        bundle.camera.target = RenderTarget::Image(viewports.images[index].clone());
+        bundle.marker = ViewportCamera { index };
+        commands.spawn_bundle(bundle);
+    }
+
+    for (handle, size) in viewports.images.iter().zip(viewports.sizes).take(count) {
+        let current = images
+            .get(handle)
+            .map(|image| image.texture_descriptor.size);
+        if matches!(current, Some(current) if current.width != size.x || current.height != size.y) {
+            if let Some(image) = images.get_mut(handle) {
+                image.resize(Extent3d {
+                    width: size.x,
+                    height: size.y,
+                    depth_or_array_layers: 1,
+                });
+            }
+        }
+    }
+
+    let (main_transform, orbit, main_projection) = match main.iter().next() {
+        Some(main) => main,
+        None => return,
+    };
+    let tcp = active
+        .entity
+        .and_then(|robot| robots.get(robot).ok())
+        .map(|manipulability| manipulability.tcp);
+    for (entity, viewport, mut transform, mut camera, projection) in cameras.iter_mut() {
+        let view = settings.views[viewport.index];
+        let (target, orthographic) = match (view.preset(), tcp) {
+            // Engineering views are orthographic around the focus of the main camera
+            (Some(preset), _) => {
+                let (yaw, pitch) = preset.angles();
+                let orbit = OrbitCamera {
+                    yaw,
+                    pitch,
+                    ..*orbit
+                };
+                (orbit.view_transform(true), true)
+            }
+            // The camera looks along its -Z, the tool along its +Z
+            (None, Some(tcp)) if view == ViewportView::Tcp => (
+                Transform {
+                    translation: tcp.translation,
+                    rotation: tcp.rotation * Quat::from_rotation_y(std::f32::consts::PI),
+                    ..Default::default()
+                },
+                false,
+            ),
+            _ => (*main_transform, main_projection.is_some()),
+        };
+        if *transform != target {
+            *transform = target;
+        }
+        set_projection(
+            &mut commands,
+            entity,
+            &mut camera,
+            projection,
+            orthographic,
+            orbit.orthographic_scale(),
+        );
+    }
+}